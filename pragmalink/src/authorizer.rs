@@ -0,0 +1,195 @@
+use crate::types::{ConnectionAuthorizationRequest, ReceivedConnection};
+use auth_rs::AuthorityCertificate;
+use std::collections::HashSet;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+/// Answers the connection authorization requests by verifying the auth-rs certificate of each peer:
+/// the certificate must certify the peer identify public key and be signed by a trusted certifier
+pub struct CertificateAuthorizer {
+    /// The hex-encoded public keys of the trusted certifiers
+    trusted_certifiers: HashSet<String>,
+    /// The ids of the authorized peers, if caching is enabled
+    cache: Option<HashSet<String>>,
+}
+
+impl CertificateAuthorizer {
+    /// Create an authorizer trusting a set of hex-encoded certifier public keys
+    pub fn new(trusted_certifiers: HashSet<String>) -> Self {
+        Self {
+            trusted_certifiers,
+            cache: None,
+        }
+    }
+    /// Cache the authorized peers, a peer is only verified until it is authorized.
+    /// Rejections are not cached: a rejected peer is blocked by the node, and verified again if it is unblocked
+    pub fn with_cache(self) -> Self {
+        Self {
+            cache: Some(HashSet::new()),
+            ..self
+        }
+    }
+    /// Verify the certificate of a connection against the trusted certifiers
+    pub fn verify(&self, connection: &ReceivedConnection) -> anyhow::Result<()> {
+        let certificate = connection
+            .certificate
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No certificate provided"))?;
+        let certificate = AuthorityCertificate::try_from(certificate.as_slice())?;
+        let certified_pubkey = hex::encode(connection.pubkey);
+        for certifier_pubkey in &self.trusted_certifiers {
+            if certificate
+                .verify(certified_pubkey.clone(), certifier_pubkey.clone())
+                .is_ok()
+            {
+                return Ok(());
+            }
+        }
+        Err(anyhow::anyhow!(
+            "Certificate not valid for any trusted certifier"
+        ))
+    }
+    /// The verdict for a connection, true if the peer is in the cache
+    pub fn authorize(&mut self, connection: &ReceivedConnection) -> bool {
+        if self
+            .cache
+            .as_ref()
+            .is_some_and(|cache| cache.contains(&connection.peer_id))
+        {
+            return true;
+        }
+        let verdict = match self.verify(connection) {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::warn!("Invalid certificate for peer {}: {}", connection.peer_id, e);
+                false
+            }
+        };
+        if verdict && let Some(cache) = self.cache.as_mut() {
+            cache.insert(connection.peer_id.clone());
+        }
+        verdict
+    }
+    /// Answer the authorization requests until the node stops
+    pub async fn run(
+        mut self,
+        mut connection_authorization_rx: mpsc::Receiver<ConnectionAuthorizationRequest>,
+    ) {
+        while let Some((connection, responder)) = connection_authorization_rx.recv().await {
+            let verdict = self.authorize(&connection);
+            let _ = responder.send(verdict);
+        }
+    }
+    /// Spawn a task answering the authorization requests
+    pub fn spawn(
+        self,
+        connection_authorization_rx: mpsc::Receiver<ConnectionAuthorizationRequest>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(self.run(connection_authorization_rx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use auth_rs::AuthorityCertificateBuilder;
+    use ed25519_dalek::SigningKey;
+
+    fn certificate(certified: &SigningKey, certifier: &SigningKey) -> Vec<u8> {
+        AuthorityCertificateBuilder::default()
+            .for_authority(certified.verifying_key())
+            .from_certifier(certifier.clone())
+            .build()
+            .sign_certified(hex::encode(certified.to_bytes()))
+            .unwrap()
+            .serialize_protobuf()
+    }
+
+    fn connection(pubkey: &SigningKey, certificate: Option<Vec<u8>>) -> ReceivedConnection {
+        ReceivedConnection {
+            peer_id: "peer".to_string(),
+            pubkey: pubkey.verifying_key().to_bytes(),
+            listen_addrs: Vec::new(),
+            observed_addr: String::new(),
+            certificate,
+        }
+    }
+
+    fn authorizer(certifier: &SigningKey) -> CertificateAuthorizer {
+        CertificateAuthorizer::new(HashSet::from([hex::encode(
+            certifier.verifying_key().to_bytes(),
+        )]))
+    }
+
+    #[test]
+    fn verify_trusted_certificate() {
+        let (certified, certifier) = (
+            SigningKey::from_bytes(&[1; 32]),
+            SigningKey::from_bytes(&[2; 32]),
+        );
+        let connection = connection(&certified, Some(certificate(&certified, &certifier)));
+        assert!(authorizer(&certifier).verify(&connection).is_ok());
+    }
+
+    #[test]
+    fn reject_untrusted_certifier() {
+        let (certified, certifier) = (
+            SigningKey::from_bytes(&[1; 32]),
+            SigningKey::from_bytes(&[2; 32]),
+        );
+        let connection = connection(&certified, Some(certificate(&certified, &certifier)));
+        let trusted = SigningKey::from_bytes(&[3; 32]);
+        assert!(authorizer(&trusted).verify(&connection).is_err());
+    }
+
+    #[test]
+    fn reject_certificate_of_another_key() {
+        let (certified, certifier) = (
+            SigningKey::from_bytes(&[1; 32]),
+            SigningKey::from_bytes(&[2; 32]),
+        );
+        let other = SigningKey::from_bytes(&[3; 32]);
+        let connection = connection(&other, Some(certificate(&certified, &certifier)));
+        assert!(authorizer(&certifier).verify(&connection).is_err());
+    }
+
+    #[test]
+    fn reject_missing_certificate() {
+        let (certified, certifier) = (
+            SigningKey::from_bytes(&[1; 32]),
+            SigningKey::from_bytes(&[2; 32]),
+        );
+        assert!(
+            authorizer(&certifier)
+                .verify(&connection(&certified, None))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn authorize_cached_peer() {
+        let (certified, certifier) = (
+            SigningKey::from_bytes(&[1; 32]),
+            SigningKey::from_bytes(&[2; 32]),
+        );
+        let mut authorizer = authorizer(&certifier).with_cache();
+        assert!(authorizer.authorize(&connection(
+            &certified,
+            Some(certificate(&certified, &certifier))
+        )));
+        assert!(authorizer.authorize(&connection(&certified, None)));
+    }
+
+    #[test]
+    fn do_not_cache_rejections() {
+        let (certified, certifier) = (
+            SigningKey::from_bytes(&[1; 32]),
+            SigningKey::from_bytes(&[2; 32]),
+        );
+        let mut authorizer = authorizer(&certifier).with_cache();
+        assert!(!authorizer.authorize(&connection(&certified, None)));
+        assert!(authorizer.authorize(&connection(
+            &certified,
+            Some(certificate(&certified, &certifier))
+        )));
+    }
+}
@@ -1,45 +1,100 @@
+use crate::{
+    certificate::{CERTIFICATE_PROTOCOL, CertificateCodec},
+    config::P2pNodeConfig,
+    direct::{DIRECT_PROTOCOL, DirectCodec},
+    store::PersistentStore,
+};
 use libp2p::{
-    StreamProtocol, identify,
+    allow_block_list::{self, AllowedPeers, BlockedPeers},
+    connection_limits, identify,
     identity::Keypair,
-    kad::{self, store::MemoryStore},
-    swarm::NetworkBehaviour,
+    kad, mdns,
+    request_response::{self, ProtocolSupport},
+    swarm::{NetworkBehaviour, behaviour::toggle::Toggle},
 };
 use libp2p_gossipsub::MessageAuthenticity;
-use std::time::Duration;
 
-const PROTOCOL_VERSION: &str = "/pragma/kad/0.1.0";
 const AGENT_VERSION: &str = "/pragma-node/0.1.0";
 
 #[derive(NetworkBehaviour)]
 pub struct P2pBehavior {
+    pub connection_limits: connection_limits::Behaviour,
+    /// Only enabled if an allow list is configured, connections to other peers are then denied
+    pub allowed_peers: Toggle<allow_block_list::Behaviour<AllowedPeers>>,
+    pub blocked_peers: allow_block_list::Behaviour<BlockedPeers>,
     pub gossipsub: libp2p_gossipsub::Behaviour,
-    pub kademlia: libp2p::kad::Behaviour<MemoryStore>,
+    pub kademlia: libp2p::kad::Behaviour<PersistentStore>,
     pub identify: identify::Behaviour,
+    /// Only enabled if mDNS discovery is configured, discovers peers on the local network
+    pub mdns: Toggle<mdns::tokio::Behaviour>,
+    /// Exchanges the certificates once the peers are identified
+    pub certificate_exchange: request_response::Behaviour<CertificateCodec>,
+    /// Point-to-point requests between peers
+    pub direct_messages: request_response::Behaviour<DirectCodec>,
 }
 
 impl P2pBehavior {
-    pub fn new(local_keypair: Keypair, certificate: Option<String>) -> anyhow::Result<Self> {
+    pub fn new(local_keypair: Keypair, config: &P2pNodeConfig) -> anyhow::Result<Self> {
         let local_peer_id = local_keypair.public().into();
         Ok(Self {
+            connection_limits: connection_limits::Behaviour::new(
+                config.connection_limits.to_limits(),
+            ),
+            allowed_peers: config
+                .allowed_peers
+                .as_ref()
+                .map(|allowed_peers| {
+                    let mut behaviour = allow_block_list::Behaviour::default();
+                    for peer_id in allowed_peers {
+                        behaviour.allow_peer(*peer_id);
+                    }
+                    behaviour
+                })
+                .into(),
+            blocked_peers: {
+                let mut behaviour = allow_block_list::Behaviour::default();
+                for peer_id in &config.blocked_peers {
+                    behaviour.block_peer(*peer_id);
+                }
+                behaviour
+            },
             identify: identify::Behaviour::new(
                 identify::Config::new(identify::PROTOCOL_NAME.to_string(), local_keypair.public())
-                    .with_agent_version(format!(
-                        "{}/{}",
-                        AGENT_VERSION,
-                        certificate.unwrap_or("uncertified".to_string())
-                    )),
+                    .with_agent_version(AGENT_VERSION.to_string()),
+            ),
+            mdns: config
+                .mdns
+                .then(|| mdns::tokio::Behaviour::new(mdns::Config::default(), local_peer_id))
+                .transpose()?
+                .into(),
+            certificate_exchange: request_response::Behaviour::new(
+                [(CERTIFICATE_PROTOCOL, ProtocolSupport::Full)],
+                request_response::Config::default(),
+            ),
+            direct_messages: request_response::Behaviour::with_codec(
+                DirectCodec::new(config.direct_messaging_settings.max_message_size),
+                [(DIRECT_PROTOCOL, ProtocolSupport::Full)],
+                request_response::Config::default()
+                    .with_request_timeout(config.direct_messaging_settings.timeout),
             ),
             kademlia: {
-                let protocol = StreamProtocol::try_from_owned(PROTOCOL_VERSION.into())
-                    .expect("Invalid kad stream protocol");
-                let mut cfg = kad::Config::new(protocol);
-                cfg.set_periodic_bootstrap_interval(Some(Duration::from_millis(500)));
-                kad::Behaviour::with_config(local_peer_id, MemoryStore::new(local_peer_id), cfg)
+                let cfg = config.kademlia_settings.to_config()?;
+                let store = PersistentStore::open(local_peer_id, config.persistence_dir.clone())?;
+                let mut kademlia = kad::Behaviour::with_config(local_peer_id, store, cfg);
+                kademlia.set_mode(config.kademlia_settings.mode);
+                kademlia
             },
             gossipsub: {
                 let privacy = MessageAuthenticity::Signed(local_keypair.clone());
-                libp2p_gossipsub::Behaviour::new(privacy, libp2p_gossipsub::Config::default())
-                    .map_err(|err| anyhow::anyhow!("Error making gossipsub config: {err}"))?
+                let cfg = config.gossipsub_settings.to_config(&config.message_id)?;
+                let mut gossipsub = libp2p_gossipsub::Behaviour::new(privacy, cfg)
+                    .map_err(|err| anyhow::anyhow!("Error making gossipsub config: {err}"))?;
+                if let Some(peer_score) = &config.peer_score {
+                    gossipsub
+                        .with_peer_score(peer_score.to_params(), peer_score.thresholds.clone())
+                        .map_err(|err| anyhow::anyhow!("Error making peer score config: {err}"))?;
+                }
+                gossipsub
             },
         })
     }
@@ -0,0 +1,165 @@
+use crate::config::BootstrapSettings;
+use std::time::{Duration, Instant, SystemTime};
+
+/// The bootstrap state of the node, returned by [`crate::handle::P2pHandle::bootstrap_status`]
+#[derive(Clone, Debug)]
+pub struct BootstrapStatus {
+    /// The number of connected peers accepted by the connection authorization
+    pub connected_peers: usize,
+    /// The number of authorized peers below which the bootstrap nodes are redialed
+    pub min_peers: usize,
+    /// Whether the node has enough authorized peers
+    pub healthy: bool,
+    /// The number of bootstrap attempts since the node was last healthy
+    pub attempts: u32,
+    /// When the bootstrap nodes were last dialed
+    pub last_attempt: Option<SystemTime>,
+    /// The delay before the next attempt, if the node is not healthy
+    pub next_attempt_in: Option<Duration>,
+}
+
+/// Redials the bootstrap nodes with exponential backoff and jitter while the node has too few authorized peers
+pub(crate) struct BootstrapManager {
+    settings: BootstrapSettings,
+    attempts: u32,
+    last_attempt: Option<SystemTime>,
+    /// When the bootstrap nodes can be redialed, None if the delay is too long to be scheduled
+    next_attempt: Option<Instant>,
+}
+
+impl BootstrapManager {
+    /// Create the manager, fails if the settings are invalid
+    pub(crate) fn new(settings: BootstrapSettings) -> anyhow::Result<Self> {
+        settings.validate()?;
+        Ok(Self {
+            settings,
+            attempts: 0,
+            last_attempt: None,
+            next_attempt: Some(Instant::now()),
+        })
+    }
+    pub(crate) fn check_interval(&self) -> Duration {
+        self.settings.check_interval
+    }
+    /// Record a dial of the bootstrap nodes and schedule the next one
+    pub(crate) fn attempted(&mut self) {
+        let delay = self.delay(rand::random());
+        self.attempts = self.attempts.saturating_add(1);
+        self.last_attempt = Some(SystemTime::now());
+        self.next_attempt = Instant::now().checked_add(delay);
+    }
+    /// The delay before the next attempt: the backoff, plus a random part of it drawn from a number in [0, 1)
+    fn delay(&self, random: f64) -> Duration {
+        let backoff = self
+            .settings
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(self.attempts))
+            .min(self.settings.max_backoff);
+        let jitter =
+            Duration::try_from_secs_f64(backoff.as_secs_f64() * random * self.settings.jitter)
+                .unwrap_or(Duration::MAX);
+        backoff.saturating_add(jitter)
+    }
+    /// Whether the bootstrap nodes should be redialed, the backoff is reset once the node is healthy
+    pub(crate) fn should_redial(&mut self, connected_peers: usize) -> bool {
+        if connected_peers >= self.settings.min_peers {
+            self.attempts = 0;
+            return false;
+        }
+        self.next_attempt
+            .is_some_and(|next_attempt| Instant::now() >= next_attempt)
+    }
+    pub(crate) fn status(&self, connected_peers: usize) -> BootstrapStatus {
+        let healthy = connected_peers >= self.settings.min_peers;
+        BootstrapStatus {
+            connected_peers,
+            min_peers: self.settings.min_peers,
+            healthy,
+            attempts: self.attempts,
+            last_attempt: self.last_attempt,
+            next_attempt_in: (!healthy).then(|| {
+                self.next_attempt.map_or(Duration::MAX, |next_attempt| {
+                    next_attempt.saturating_duration_since(Instant::now())
+                })
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager(settings: BootstrapSettings) -> BootstrapManager {
+        BootstrapManager::new(settings).unwrap()
+    }
+
+    #[test]
+    fn double_the_backoff_up_to_the_maximum() {
+        let mut manager = manager(BootstrapSettings {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+            jitter: 0.5,
+            ..Default::default()
+        });
+        let mut delays = Vec::new();
+        for _ in 0..5 {
+            delays.push(manager.delay(0.0));
+            manager.attempted();
+        }
+        assert_eq!(delays, [1, 2, 4, 5, 5].map(Duration::from_secs));
+        assert_eq!(manager.delay(0.5), Duration::from_millis(6250));
+        assert!(!manager.should_redial(0));
+        assert!(manager.status(0).next_attempt_in.unwrap() <= Duration::from_millis(7500));
+        assert!(!manager.should_redial(1));
+        assert_eq!(manager.delay(0.0), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn schedule_unbounded_backoff() {
+        let mut large = manager(BootstrapSettings {
+            max_backoff: Duration::MAX,
+            jitter: 10.0,
+            ..Default::default()
+        });
+        for _ in 0..40 {
+            large.attempted();
+        }
+        assert!(large.delay(0.9) >= Duration::from_secs(u32::MAX.into()));
+        let mut overflowing = manager(BootstrapSettings {
+            initial_backoff: Duration::MAX,
+            max_backoff: Duration::MAX,
+            ..Default::default()
+        });
+        overflowing.attempted();
+        assert_eq!(overflowing.delay(0.9), Duration::MAX);
+        assert!(!overflowing.should_redial(0));
+        assert_eq!(overflowing.status(0).next_attempt_in, Some(Duration::MAX));
+    }
+
+    #[test]
+    fn reject_invalid_settings() {
+        let invalid = [
+            BootstrapSettings {
+                jitter: -0.1,
+                ..Default::default()
+            },
+            BootstrapSettings {
+                jitter: f64::NAN,
+                ..Default::default()
+            },
+            BootstrapSettings {
+                initial_backoff: Duration::from_secs(2),
+                max_backoff: Duration::from_secs(1),
+                ..Default::default()
+            },
+            BootstrapSettings {
+                check_interval: Duration::ZERO,
+                ..Default::default()
+            },
+        ];
+        for settings in invalid {
+            assert!(BootstrapManager::new(settings).is_err());
+        }
+    }
+}
@@ -1,15 +1,43 @@
 use crate::{
-    types::{P2pRequest, ReceivedConnection, ReceivedMessage}, P2pNode, DEFAULT_LISTENING_PORT
+    DEFAULT_LISTENING_PORT, P2pNode,
+    config::{
+        AddressSettings, AuthorizationSettings, BootstrapSettings, ConnectionLimitsSettings,
+        DirectMessagingSettings, GossipsubSettings, KademliaSettings, MessageIdStrategy,
+        P2pNodeConfig, PeerScoreSettings, TransportPreference,
+    },
+    handle::P2pHandle,
+    types::{ConnectionAuthorizationRequest, InboundRequest, MessageValidationRequest},
+};
+use libp2p::{Multiaddr, PeerId, identity::Keypair, multiaddr::Protocol};
+use std::{
+    collections::HashSet,
+    net::{Ipv4Addr, Ipv6Addr},
+    path::PathBuf,
 };
-use libp2p::{Multiaddr, identity::Keypair, multiaddr::Protocol};
-use std::collections::HashSet;
 
+#[derive(Default)]
 pub struct P2pNodeBuilder {
     keypair: Option<String>,
-    listening_address: Option<String>,
+    listening_addresses: Option<HashSet<String>>,
+    transport_preference: Option<TransportPreference>,
     bootstrap_nodes: Option<HashSet<String>>,
     indentify_certificate: Option<String>,
     gossipsub_topics: Option<HashSet<String>>,
+    message_validator: Option<tokio::sync::mpsc::Sender<MessageValidationRequest>>,
+    gossipsub_settings: Option<GossipsubSettings>,
+    peer_score: Option<PeerScoreSettings>,
+    message_id: Option<MessageIdStrategy>,
+    connection_limits: Option<ConnectionLimitsSettings>,
+    allowed_peers: Option<HashSet<String>>,
+    blocked_peers: Option<HashSet<String>>,
+    authorization_settings: Option<AuthorizationSettings>,
+    address_settings: Option<AddressSettings>,
+    mdns: Option<bool>,
+    bootstrap_settings: Option<BootstrapSettings>,
+    kademlia_settings: Option<KademliaSettings>,
+    persistence_dir: Option<PathBuf>,
+    request_handler: Option<tokio::sync::mpsc::Sender<InboundRequest>>,
+    direct_messaging_settings: Option<DirectMessagingSettings>,
 }
 
 impl P2pNodeBuilder {
@@ -17,10 +45,26 @@ impl P2pNodeBuilder {
     pub fn new() -> Self {
         Self {
             keypair: None,
-            listening_address: None,
+            listening_addresses: None,
+            transport_preference: None,
             bootstrap_nodes: None,
             indentify_certificate: None,
             gossipsub_topics: None,
+            message_validator: None,
+            gossipsub_settings: None,
+            peer_score: None,
+            message_id: None,
+            connection_limits: None,
+            allowed_peers: None,
+            blocked_peers: None,
+            authorization_settings: None,
+            address_settings: None,
+            mdns: None,
+            bootstrap_settings: None,
+            kademlia_settings: None,
+            persistence_dir: None,
+            request_handler: None,
+            direct_messaging_settings: None,
         }
     }
     /// Define an ed25519 keypair, encoded in hexadecimals
@@ -30,10 +74,26 @@ impl P2pNodeBuilder {
             ..self
         }
     }
-    /// Define a listening address: example: "/ip4/0.0.0.0/tcp/1123"
+    /// Add a listening address: example: "/ip4/0.0.0.0/tcp/1123", "/ip6/::/udp/1123/quic-v1" or "/ip4/0.0.0.0/tcp/1124/ws"
     pub fn with_listening_address(self, listening_address: String) -> Self {
+        let mut listening_addresses = self.listening_addresses.unwrap_or_default();
+        listening_addresses.insert(listening_address);
+        Self {
+            listening_addresses: Some(listening_addresses),
+            ..self
+        }
+    }
+    /// Define a set of listening addresses, replacing the default addresses: TCP and QUIC on IPv4 and IPv6, depending on the transport preference
+    pub fn with_listening_addresses(self, listening_addresses: HashSet<String>) -> Self {
         Self {
-            listening_address: Some(listening_address),
+            listening_addresses: Some(listening_addresses),
+            ..self
+        }
+    }
+    /// Define which transports the node uses, and which one is dialed first
+    pub fn with_transport_preference(self, transport_preference: TransportPreference) -> Self {
+        Self {
+            transport_preference: Some(transport_preference),
             ..self
         }
     }
@@ -44,7 +104,7 @@ impl P2pNodeBuilder {
             ..self
         }
     }
-    /// Define an identity information that will be used to identify the node to other peers
+    /// Define the hex-encoded certificate sent to other peers through the certificate exchange
     pub fn with_indentify_certificate(self, indentify_certificate: String) -> Self {
         Self {
             indentify_certificate: Some(indentify_certificate),
@@ -58,13 +118,133 @@ impl P2pNodeBuilder {
             ..self
         }
     }
+    /// Define a channel to validate received messages: each message is sent with a oneshot channel
+    /// to answer Accept / Reject / Ignore, and is only propagated and delivered once accepted
+    pub fn with_message_validator(
+        self,
+        message_validator: tokio::sync::mpsc::Sender<MessageValidationRequest>,
+    ) -> Self {
+        Self {
+            message_validator: Some(message_validator),
+            ..self
+        }
+    }
+    /// Define the gossipsub settings: mesh sizes, heartbeat, max message size...
+    /// see [`GossipsubSettings::low_latency`] and [`GossipsubSettings::large_payload`] for presets
+    pub fn with_gossipsub_settings(self, gossipsub_settings: GossipsubSettings) -> Self {
+        Self {
+            gossipsub_settings: Some(gossipsub_settings),
+            ..self
+        }
+    }
+    /// Enable gossipsub peer scoring, with score parameters and thresholds, and per topic parameters by topic name
+    pub fn with_peer_score(self, peer_score: PeerScoreSettings) -> Self {
+        Self {
+            peer_score: Some(peer_score),
+            ..self
+        }
+    }
+    /// Define how gossipsub message ids are computed, use [`MessageIdStrategy::ContentHash`] to deliver
+    /// only once the same data published by different nodes
+    pub fn with_message_id(self, message_id: MessageIdStrategy) -> Self {
+        Self {
+            message_id: Some(message_id),
+            ..self
+        }
+    }
+    /// Define the connection limits: pending and established connections, connections per peer...
+    pub fn with_connection_limits(self, connection_limits: ConnectionLimitsSettings) -> Self {
+        Self {
+            connection_limits: Some(connection_limits),
+            ..self
+        }
+    }
+    /// Define a set of peer ids allowed to connect, connections from other peers are denied
+    pub fn with_allowed_peers(self, allowed_peers: HashSet<String>) -> Self {
+        Self {
+            allowed_peers: Some(allowed_peers),
+            ..self
+        }
+    }
+    /// Define a set of peer ids that cannot connect
+    pub fn with_blocked_peers(self, blocked_peers: HashSet<String>) -> Self {
+        Self {
+            blocked_peers: Some(blocked_peers),
+            ..self
+        }
+    }
+    /// Define how long the node waits for a connection authorization verdict, and the verdict applied after this timeout
+    pub fn with_authorization_settings(
+        self,
+        authorization_settings: AuthorizationSettings,
+    ) -> Self {
+        Self {
+            authorization_settings: Some(authorization_settings),
+            ..self
+        }
+    }
+    /// Define which peer addresses are added to kademlia and how external addresses are confirmed
+    pub fn with_address_settings(self, address_settings: AddressSettings) -> Self {
+        Self {
+            address_settings: Some(address_settings),
+            ..self
+        }
+    }
+    /// Enable the discovery of peers on the local network with mDNS, disabled by default
+    pub fn with_mdns(self, mdns: bool) -> Self {
+        Self {
+            mdns: Some(mdns),
+            ..self
+        }
+    }
+    /// Define when and how often the bootstrap nodes are redialed
+    pub fn with_bootstrap_settings(self, bootstrap_settings: BootstrapSettings) -> Self {
+        Self {
+            bootstrap_settings: Some(bootstrap_settings),
+            ..self
+        }
+    }
+    /// Define the kademlia settings: mode, protocol name, replication, timeouts and bootstrap interval
+    pub fn with_kademlia_settings(self, kademlia_settings: KademliaSettings) -> Self {
+        Self {
+            kademlia_settings: Some(kademlia_settings),
+            ..self
+        }
+    }
+    /// Save the kademlia records and routing table to a directory, restored when the node restarts
+    pub fn with_persistence_dir(self, persistence_dir: PathBuf) -> Self {
+        Self {
+            persistence_dir: Some(persistence_dir),
+            ..self
+        }
+    }
+    /// Define a channel to answer the direct requests of other peers, dropped without a handler
+    pub fn with_request_handler(
+        self,
+        request_handler: tokio::sync::mpsc::Sender<InboundRequest>,
+    ) -> Self {
+        Self {
+            request_handler: Some(request_handler),
+            ..self
+        }
+    }
+    /// Define the direct messaging timeout and maximum message size
+    pub fn with_direct_messaging_settings(
+        self,
+        direct_messaging_settings: DirectMessagingSettings,
+    ) -> Self {
+        Self {
+            direct_messaging_settings: Some(direct_messaging_settings),
+            ..self
+        }
+    }
+    /// Build the node, with a handle to control it once running and the receiver of connection authorization requests
     pub fn build(
         self,
     ) -> anyhow::Result<(
         P2pNode,
-        tokio::sync::broadcast::Receiver<ReceivedMessage>,
-        tokio::sync::mpsc::Sender<P2pRequest>,
-        tokio::sync::mpsc::Receiver<(ReceivedConnection, tokio::sync::oneshot::Sender<bool>)>,
+        P2pHandle,
+        tokio::sync::mpsc::Receiver<ConnectionAuthorizationRequest>,
     )> {
         let keypair = match self.keypair {
             Some(keypair) => Keypair::ed25519_from_bytes(hex::decode(keypair)?)
@@ -74,17 +254,36 @@ impl P2pNodeBuilder {
                 Keypair::generate_ed25519()
             }
         };
-        let listening_address = match self.listening_address {
-            Some(listening_address) => listening_address.parse::<Multiaddr>()?,
+        let transport_preference = self.transport_preference.unwrap_or_default();
+        let listening_addresses = match self.listening_addresses {
+            Some(listening_addresses) => listening_addresses
+                .into_iter()
+                .map(|addr| addr.parse::<Multiaddr>())
+                .collect::<Result<HashSet<Multiaddr>, _>>()?,
             None => {
                 tracing::warn!("No listening address provided for node, using default");
-                "/ip4/0.0.0.0".parse::<Multiaddr>()
-                .unwrap() //safe because constant
-                .with(Protocol::Tcp(DEFAULT_LISTENING_PORT))
+                let unspecified: [Multiaddr; 2] = [
+                    Protocol::Ip4(Ipv4Addr::UNSPECIFIED).into(),
+                    Protocol::Ip6(Ipv6Addr::UNSPECIFIED).into(),
+                ];
+                unspecified
+                    .into_iter()
+                    .flat_map(|ip| {
+                        [
+                            ip.clone().with(Protocol::Tcp(DEFAULT_LISTENING_PORT)),
+                            ip.with(Protocol::Udp(DEFAULT_LISTENING_PORT))
+                                .with(Protocol::QuicV1),
+                        ]
+                    })
+                    .filter(|address| transport_preference.priority(address).is_some())
+                    .collect()
             }
         };
         let bootstrap_nodes = match self.bootstrap_nodes {
-            Some(bootstrap_nodes) => bootstrap_nodes.into_iter().map(|addr| addr.parse::<Multiaddr>()).collect::<Result<HashSet<Multiaddr>, _>>()?,
+            Some(bootstrap_nodes) => bootstrap_nodes
+                .into_iter()
+                .map(|addr| addr.parse::<Multiaddr>())
+                .collect::<Result<HashSet<Multiaddr>, _>>()?,
             None => {
                 tracing::warn!("No bootstrap nodes provided for node, using empty set");
                 HashSet::new()
@@ -97,12 +296,45 @@ impl P2pNodeBuilder {
                 HashSet::new()
             }
         };
+        let allowed_peers = self
+            .allowed_peers
+            .map(|allowed_peers| {
+                allowed_peers
+                    .iter()
+                    .map(|peer_id| peer_id.parse::<PeerId>())
+                    .collect::<Result<HashSet<PeerId>, _>>()
+            })
+            .transpose()?;
+        let blocked_peers = self
+            .blocked_peers
+            .unwrap_or_default()
+            .iter()
+            .map(|peer_id| peer_id.parse::<PeerId>())
+            .collect::<Result<HashSet<PeerId>, _>>()?;
         P2pNode::new(
             keypair,
-            listening_address,
-            bootstrap_nodes,
-            self.indentify_certificate,
-            gossipsub_topics,
+            P2pNodeConfig {
+                listening_addresses,
+                transport_preference,
+                bootstrap_nodes,
+                identify_certificate: self.indentify_certificate,
+                gossipsub_topics,
+                gossipsub_settings: self.gossipsub_settings.unwrap_or_default(),
+                message_id: self.message_id.unwrap_or_default(),
+                peer_score: self.peer_score,
+                message_validator_tx: self.message_validator,
+                connection_limits: self.connection_limits.unwrap_or_default(),
+                allowed_peers,
+                blocked_peers,
+                authorization_settings: self.authorization_settings.unwrap_or_default(),
+                address_settings: self.address_settings.unwrap_or_default(),
+                mdns: self.mdns.unwrap_or_default(),
+                bootstrap_settings: self.bootstrap_settings.unwrap_or_default(),
+                kademlia_settings: self.kademlia_settings.unwrap_or_default(),
+                persistence_dir: self.persistence_dir,
+                request_handler_tx: self.request_handler,
+                direct_messaging_settings: self.direct_messaging_settings.unwrap_or_default(),
+            },
         )
     }
 }
@@ -0,0 +1,262 @@
+use crate::{
+    framing::{read_field, write_field},
+    types::ReceivedConnection,
+};
+use async_trait::async_trait;
+use libp2p::{
+    PeerId, StreamProtocol,
+    futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    identity::{Keypair, PublicKey},
+    request_response,
+};
+use std::io;
+
+/// The certificate exchange protocol, run once a peer is identified
+pub const CERTIFICATE_PROTOCOL: StreamProtocol = StreamProtocol::new("/pragma/cert/1.0.0");
+
+/// The size of the challenge nonce, in bytes
+const NONCE_SIZE: usize = 32;
+/// The maximum size of a certificate exchange response, in bytes
+const MAX_RESPONSE_SIZE: u64 = 64 * 1024;
+
+/// A challenge sent to a peer, answered with its certificate and a proof it controls the certified key
+#[derive(Clone, Debug)]
+pub struct CertificateRequest {
+    pub nonce: [u8; NONCE_SIZE],
+}
+
+impl CertificateRequest {
+    /// A request with a random nonce
+    pub fn random() -> Self {
+        Self {
+            nonce: rand::random(),
+        }
+    }
+}
+
+/// The certificate of a peer, with the signature of the challenge by its identity key
+#[derive(Clone, Debug)]
+pub struct CertificateResponse {
+    /// The protobuf encoded public key of the peer
+    pub public_key: Vec<u8>,
+    /// The signature of the challenge
+    pub signature: Vec<u8>,
+    /// The binary protobuf certificate, None if the peer is not certified
+    pub certificate: Option<Vec<u8>>,
+}
+
+impl CertificateResponse {
+    /// Answer the challenge of a peer, signing it with the local keypair
+    pub fn new(
+        keypair: &Keypair,
+        request: &CertificateRequest,
+        challenger: &PeerId,
+        certificate: Option<Vec<u8>>,
+    ) -> Result<Self, libp2p::identity::SigningError> {
+        let local_peer_id = keypair.public().to_peer_id();
+        let signature = keypair.sign(&challenge(&request.nonce, challenger, &local_peer_id))?;
+        Ok(Self {
+            public_key: keypair.public().encode_protobuf(),
+            signature,
+            certificate,
+        })
+    }
+    /// Check that the response was signed for this challenge by the key the peer identified with,
+    /// returning the verified key. The certificate must certify this key to be accepted
+    pub fn verify(
+        &self,
+        request: &CertificateRequest,
+        local_peer_id: &PeerId,
+        peer_id: &PeerId,
+        identify_key: &PublicKey,
+    ) -> anyhow::Result<PublicKey> {
+        let public_key = PublicKey::try_decode_protobuf(&self.public_key)?;
+        if public_key.to_peer_id() != *peer_id {
+            anyhow::bail!("The certificate public key does not match the peer id");
+        }
+        if public_key != *identify_key {
+            anyhow::bail!("The certificate public key does not match the identify public key");
+        }
+        if !public_key.verify(
+            &challenge(&request.nonce, local_peer_id, peer_id),
+            &self.signature,
+        ) {
+            anyhow::bail!("Invalid challenge signature");
+        }
+        Ok(public_key)
+    }
+}
+
+/// The signed payload: protocol name, nonce, challenger and responder peer ids
+fn challenge(nonce: &[u8], challenger: &PeerId, responder: &PeerId) -> Vec<u8> {
+    [
+        CERTIFICATE_PROTOCOL.as_ref().as_bytes(),
+        nonce,
+        &challenger.to_bytes(),
+        &responder.to_bytes(),
+    ]
+    .concat()
+}
+
+/// A certificate exchange waiting for the peer response, the peer is authorized once the response is verified
+pub(crate) struct PendingExchange {
+    pub request_id: request_response::OutboundRequestId,
+    pub request: CertificateRequest,
+    pub connection: ReceivedConnection,
+    pub info: libp2p::identify::Info,
+}
+
+/// Encodes the certificate exchange messages, the response fields are length prefixed
+#[derive(Clone, Debug, Default)]
+pub struct CertificateCodec;
+
+#[async_trait]
+impl request_response::Codec for CertificateCodec {
+    type Protocol = StreamProtocol;
+    type Request = CertificateRequest;
+    type Response = CertificateResponse;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let mut nonce = [0; NONCE_SIZE];
+        io.read_exact(&mut nonce).await?;
+        Ok(CertificateRequest { nonce })
+    }
+    async fn read_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let mut bytes = Vec::new();
+        io.take(MAX_RESPONSE_SIZE).read_to_end(&mut bytes).await?;
+        let mut fields = bytes.as_slice();
+        let public_key = read_field(&mut fields)?;
+        let signature = read_field(&mut fields)?;
+        let certificate = read_field(&mut fields)?;
+        Ok(CertificateResponse {
+            public_key,
+            signature,
+            certificate: (!certificate.is_empty()).then_some(certificate),
+        })
+    }
+    async fn write_request<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        request: Self::Request,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        io.write_all(&request.nonce).await?;
+        io.close().await
+    }
+    async fn write_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        response: Self::Response,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let mut bytes = Vec::new();
+        write_field(&mut bytes, &response.public_key)?;
+        write_field(&mut bytes, &response.signature)?;
+        write_field(
+            &mut bytes,
+            response.certificate.as_deref().unwrap_or_default(),
+        )?;
+        io.write_all(&bytes).await?;
+        io.close().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::{identify, swarm::ConnectionId};
+
+    #[test]
+    fn verify_valid_response() {
+        let challenger = PeerId::random();
+        let keypair = Keypair::generate_ed25519();
+        let request = CertificateRequest::random();
+        let response =
+            CertificateResponse::new(&keypair, &request, &challenger, Some(vec![1, 2, 3])).unwrap();
+        let peer_id = keypair.public().to_peer_id();
+        let public_key = response
+            .verify(&request, &challenger, &peer_id, &keypair.public())
+            .unwrap();
+        assert_eq!(public_key, keypair.public());
+    }
+
+    #[test]
+    fn reject_response_for_another_challenger() {
+        let keypair = Keypair::generate_ed25519();
+        let request = CertificateRequest::random();
+        let response =
+            CertificateResponse::new(&keypair, &request, &PeerId::random(), None).unwrap();
+        let peer_id = keypair.public().to_peer_id();
+        let result = response.verify(&request, &PeerId::random(), &peer_id, &keypair.public());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn reject_key_not_matching_peer_id() {
+        let challenger = PeerId::random();
+        let keypair = Keypair::generate_ed25519();
+        let request = CertificateRequest::random();
+        let response = CertificateResponse::new(&keypair, &request, &challenger, None).unwrap();
+        let other_peer_id = Keypair::generate_ed25519().public().to_peer_id();
+        let result = response.verify(&request, &challenger, &other_peer_id, &keypair.public());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn reject_replayed_certificate_with_forged_identify_key() {
+        let challenger = PeerId::random();
+        let certified = Keypair::generate_ed25519();
+        let attacker = Keypair::generate_ed25519();
+        let attacker_peer_id = attacker.public().to_peer_id();
+        // The attacker obtained the certificate of a certified node by challenging it
+        let replayed = CertificateResponse::new(
+            &certified,
+            &CertificateRequest::random(),
+            &attacker_peer_id,
+            Some(vec![1, 2, 3]),
+        )
+        .unwrap()
+        .certificate;
+        // and claims the certified key in its identify, while signing with its own key
+        let request = CertificateRequest::random();
+        let response =
+            CertificateResponse::new(&attacker, &request, &challenger, replayed).unwrap();
+        let result = response.verify(
+            &request,
+            &challenger,
+            &attacker_peer_id,
+            &certified.public(),
+        );
+        assert!(result.is_err());
+
+        let event = identify::Event::Received {
+            connection_id: ConnectionId::new_unchecked(0),
+            peer_id: attacker_peer_id,
+            info: identify::Info {
+                public_key: certified.public(),
+                protocol_version: String::new(),
+                agent_version: String::new(),
+                listen_addrs: Vec::new(),
+                protocols: Vec::new(),
+                observed_addr: "/ip4/1.2.3.4/tcp/1123".parse().unwrap(),
+            },
+        };
+        assert!(ReceivedConnection::try_from(event).is_err());
+    }
+}
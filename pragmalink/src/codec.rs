@@ -0,0 +1,59 @@
+#[cfg(any(feature = "json", feature = "bincode", feature = "prost"))]
+use crate::{
+    error::{DecodeError, EncodeError},
+    traits::Codec,
+};
+
+/// Encode payloads in JSON
+#[cfg(feature = "json")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonCodec;
+
+#[cfg(feature = "json")]
+impl<T> Codec<T> for JsonCodec
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    fn encode(&self, value: &T) -> Result<Vec<u8>, EncodeError> {
+        serde_json::to_vec(value).map_err(|e| EncodeError(e.into()))
+    }
+    fn decode(&self, bytes: &[u8]) -> Result<T, DecodeError> {
+        serde_json::from_slice(bytes).map_err(|e| DecodeError(e.into()))
+    }
+}
+
+/// Encode payloads with bincode
+#[cfg(feature = "bincode")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BincodeCodec;
+
+#[cfg(feature = "bincode")]
+impl<T> Codec<T> for BincodeCodec
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    fn encode(&self, value: &T) -> Result<Vec<u8>, EncodeError> {
+        bincode::serialize(value).map_err(|e| EncodeError(e.into()))
+    }
+    fn decode(&self, bytes: &[u8]) -> Result<T, DecodeError> {
+        bincode::deserialize(bytes).map_err(|e| DecodeError(e.into()))
+    }
+}
+
+/// Encode payloads as protobuf messages
+#[cfg(feature = "prost")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProstCodec;
+
+#[cfg(feature = "prost")]
+impl<T> Codec<T> for ProstCodec
+where
+    T: prost::Message + Default,
+{
+    fn encode(&self, value: &T) -> Result<Vec<u8>, EncodeError> {
+        Ok(value.encode_to_vec())
+    }
+    fn decode(&self, bytes: &[u8]) -> Result<T, DecodeError> {
+        T::decode(bytes).map_err(|e| DecodeError(e.into()))
+    }
+}
@@ -0,0 +1,546 @@
+use crate::types::{InboundRequest, MessageValidationRequest};
+use libp2p::{
+    Multiaddr, PeerId, StreamProtocol, connection_limits::ConnectionLimits, kad,
+    multiaddr::Protocol,
+};
+use libp2p_gossipsub::{
+    IdentTopic, Message, MessageId, PeerScoreParams, PeerScoreThresholds, TopicScoreParams,
+    ValidationMode,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    num::NonZeroUsize,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+/// The settings of a node, resolved by the [`crate::builder::P2pNodeBuilder`]
+pub struct P2pNodeConfig {
+    /// The addresses the node is listening on: TCP, QUIC or websocket, on IPv4 or IPv6
+    pub listening_addresses: HashSet<Multiaddr>,
+    /// Which transports the node uses, and which one is dialed first
+    pub transport_preference: TransportPreference,
+    /// The bootstrap nodes addresses
+    pub bootstrap_nodes: HashSet<Multiaddr>,
+    /// An optional hex-encoded certificate, sent to other peers through the certificate exchange
+    pub identify_certificate: Option<String>,
+    /// The names of the gossipsub topics to subscribe to on start
+    pub gossipsub_topics: HashSet<String>,
+    /// The gossipsub settings
+    pub gossipsub_settings: GossipsubSettings,
+    /// How the gossipsub message ids are computed
+    pub message_id: MessageIdStrategy,
+    /// The gossipsub peer scoring settings, scoring is disabled if None
+    pub peer_score: Option<PeerScoreSettings>,
+    /// An optional channel to validate received messages
+    pub message_validator_tx: Option<tokio::sync::mpsc::Sender<MessageValidationRequest>>,
+    /// The connection limits, checked before the connections are upgraded
+    pub connection_limits: ConnectionLimitsSettings,
+    /// If set, only these peers can connect
+    pub allowed_peers: Option<HashSet<PeerId>>,
+    /// Peers that cannot connect
+    pub blocked_peers: HashSet<PeerId>,
+    /// The connection authorization timeout and default verdict
+    pub authorization_settings: AuthorizationSettings,
+    /// Which peer addresses are added to kademlia and how external addresses are confirmed
+    pub address_settings: AddressSettings,
+    /// Discover peers on the local network with mDNS
+    pub mdns: bool,
+    /// When and how often the bootstrap nodes are redialed
+    pub bootstrap_settings: BootstrapSettings,
+    /// The kademlia settings
+    pub kademlia_settings: KademliaSettings,
+    /// The directory where the kademlia records and routing table are saved, kept in memory only if None
+    pub persistence_dir: Option<PathBuf>,
+    /// An optional channel to answer the direct requests of other peers
+    pub request_handler_tx: Option<tokio::sync::mpsc::Sender<InboundRequest>>,
+    /// The direct messaging timeouts and maximum message size
+    pub direct_messaging_settings: DirectMessagingSettings,
+}
+
+/// The settings of the direct request-response messaging between peers
+#[derive(Clone, Copy, Debug)]
+pub struct DirectMessagingSettings {
+    /// How long the node waits for the response of a peer
+    pub timeout: Duration,
+    /// How long the node waits for the request handler, shorter than the request timeout of the peers
+    /// so that the response is sent before they give up
+    pub handler_timeout: Duration,
+    /// Maximum size of a request or a response, in bytes
+    pub max_message_size: usize,
+}
+
+impl Default for DirectMessagingSettings {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            handler_timeout: Duration::from_secs(8),
+            max_message_size: 1024 * 1024,
+        }
+    }
+}
+
+/// The kademlia settings of the node, defaults to the libp2p kademlia defaults with a 5 minutes bootstrap interval
+#[derive(Clone, Debug)]
+pub struct KademliaSettings {
+    /// The kademlia protocol name, nodes with different protocol names do not share their routing tables
+    pub protocol_name: String,
+    /// Client or server mode, None switches automatically to server mode once an external address is confirmed
+    pub mode: Option<kad::Mode>,
+    /// Number of peers a record is replicated to
+    pub replication_factor: NonZeroUsize,
+    /// Maximum duration of a query
+    pub query_timeout: Duration,
+    /// Maximum number of peers in a k-bucket
+    pub bucket_size: NonZeroUsize,
+    /// How long the stored records are kept, forever if None
+    pub record_ttl: Option<Duration>,
+    /// Time between each automatic bootstrap of the routing table, disabled if None
+    pub bootstrap_interval: Option<Duration>,
+}
+
+impl Default for KademliaSettings {
+    fn default() -> Self {
+        Self {
+            protocol_name: "/pragma/kad/0.1.0".to_string(),
+            mode: None,
+            replication_factor: kad::K_VALUE,
+            query_timeout: Duration::from_secs(60),
+            bucket_size: kad::K_VALUE,
+            record_ttl: Some(Duration::from_secs(36 * 60 * 60)),
+            bootstrap_interval: Some(Duration::from_secs(5 * 60)),
+        }
+    }
+}
+
+impl KademliaSettings {
+    /// Settings with a protocol name dedicated to a network, such as "mainnet" or "testnet"
+    pub fn for_network(network: &str) -> Self {
+        Self {
+            protocol_name: format!("/pragma/{network}/kad/0.1.0"),
+            ..Default::default()
+        }
+    }
+    /// Build the libp2p kademlia configuration
+    pub(crate) fn to_config(&self) -> anyhow::Result<kad::Config> {
+        let protocol = StreamProtocol::try_from_owned(self.protocol_name.clone())
+            .map_err(|err| anyhow::anyhow!("Invalid kademlia protocol name: {err}"))?;
+        let mut cfg = kad::Config::new(protocol);
+        cfg.set_replication_factor(self.replication_factor)
+            .set_query_timeout(self.query_timeout)
+            .set_kbucket_size(self.bucket_size)
+            .set_record_ttl(self.record_ttl)
+            .set_periodic_bootstrap_interval(self.bootstrap_interval);
+        Ok(cfg)
+    }
+}
+
+/// The bootstrap nodes are redialed with exponential backoff while the node has fewer authorized peers than `min_peers`
+#[derive(Clone, Copy, Debug)]
+pub struct BootstrapSettings {
+    /// The number of authorized peers below which the bootstrap nodes are redialed
+    pub min_peers: usize,
+    /// The delay after the first attempt, doubled after each attempt, must not be greater than the maximum delay
+    pub initial_backoff: Duration,
+    /// The maximum delay between two attempts
+    pub max_backoff: Duration,
+    /// The random part of the delay, as a fraction of the backoff, must be positive or zero
+    pub jitter: f64,
+    /// How often the authorized peer count is checked, must not be zero
+    pub check_interval: Duration,
+}
+
+impl Default for BootstrapSettings {
+    fn default() -> Self {
+        Self {
+            min_peers: 1,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            jitter: 0.2,
+            check_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+impl BootstrapSettings {
+    /// Check that the backoff, the jitter and the check interval are valid
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        if !self.jitter.is_finite() || self.jitter < 0.0 {
+            anyhow::bail!(
+                "Invalid bootstrap jitter {}, expected a positive number",
+                self.jitter
+            );
+        }
+        if self.initial_backoff > self.max_backoff {
+            anyhow::bail!(
+                "Invalid bootstrap backoff, the initial backoff {:?} is greater than the maximum backoff {:?}",
+                self.initial_backoff,
+                self.max_backoff
+            );
+        }
+        if self.check_interval.is_zero() {
+            anyhow::bail!("Invalid bootstrap check interval, expected a non-zero duration");
+        }
+        Ok(())
+    }
+}
+
+/// Which addresses advertised by the peers are added to kademlia, and how the node confirms its external addresses
+#[derive(Clone, Copy, Debug)]
+pub struct AddressSettings {
+    /// Keep the private and link-local addresses, for nodes running on a local network
+    pub allow_private: bool,
+    /// Keep the loopback addresses, for nodes running on the same host
+    pub allow_loopback: bool,
+    /// Number of authorized peers that must observe the same address before it is confirmed as external
+    pub external_address_quorum: usize,
+}
+
+impl Default for AddressSettings {
+    fn default() -> Self {
+        Self {
+            allow_private: false,
+            allow_loopback: false,
+            external_address_quorum: 3,
+        }
+    }
+}
+
+impl AddressSettings {
+    /// Whether an address can be used by other peers to reach a node, unspecified addresses never are
+    pub fn is_routable(&self, address: &Multiaddr) -> bool {
+        match address.iter().next() {
+            Some(Protocol::Ip4(ip)) => {
+                !ip.is_unspecified()
+                    && (self.allow_loopback || !ip.is_loopback())
+                    && (self.allow_private || !(ip.is_private() || ip.is_link_local()))
+            }
+            Some(Protocol::Ip6(ip)) => {
+                !ip.is_unspecified()
+                    && (self.allow_loopback || !ip.is_loopback())
+                    && (self.allow_private || !(ip.is_unique_local() || ip.is_unicast_link_local()))
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Which transports the node uses, and which one is dialed first when a peer is reachable with both
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TransportPreference {
+    /// Only listen and dial on TCP
+    TcpOnly,
+    /// Only listen and dial on QUIC
+    QuicOnly,
+    /// Listen on both, dial QUIC addresses first: faster handshakes and no head-of-line blocking
+    #[default]
+    PreferQuic,
+    /// Listen on both, dial TCP addresses first
+    PreferTcp,
+}
+
+impl TransportPreference {
+    /// Whether the node listens and dials on TCP
+    pub fn tcp(self) -> bool {
+        self != Self::QuicOnly
+    }
+    /// Whether the node listens and dials on QUIC
+    pub fn quic(self) -> bool {
+        self != Self::TcpOnly
+    }
+    /// The dial priority of an address, lowest first, None if its transport is disabled
+    pub(crate) fn priority(self, address: &Multiaddr) -> Option<u8> {
+        match (self, is_quic(address)) {
+            (Self::TcpOnly, true) | (Self::QuicOnly, false) => None,
+            (Self::PreferQuic, false) | (Self::PreferTcp, true) => Some(1),
+            _ => Some(0),
+        }
+    }
+}
+
+/// Whether an address is a QUIC address
+pub(crate) fn is_quic(address: &Multiaddr) -> bool {
+    address
+        .iter()
+        .any(|protocol| matches!(protocol, Protocol::QuicV1 | Protocol::Quic))
+}
+
+/// How long the node waits for a connection authorization verdict, and the verdict applied after this timeout
+#[derive(Clone, Copy, Debug)]
+pub struct AuthorizationSettings {
+    /// How long the node waits for the verdict
+    pub timeout: Duration,
+    /// Accept the peer if no verdict is received in time, reject it otherwise
+    pub allow_on_timeout: bool,
+}
+
+impl Default for AuthorizationSettings {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            allow_on_timeout: false,
+        }
+    }
+}
+
+/// The connection limits of the node, no limit if None
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConnectionLimitsSettings {
+    /// Maximum number of incoming connections being established
+    pub max_pending_incoming: Option<u32>,
+    /// Maximum number of outgoing connections being established
+    pub max_pending_outgoing: Option<u32>,
+    /// Maximum number of established incoming connections
+    pub max_established_incoming: Option<u32>,
+    /// Maximum number of established outgoing connections
+    pub max_established_outgoing: Option<u32>,
+    /// Maximum number of established connections
+    pub max_established: Option<u32>,
+    /// Maximum number of established connections with a single peer
+    pub max_established_per_peer: Option<u32>,
+}
+
+impl ConnectionLimitsSettings {
+    pub(crate) fn to_limits(self) -> ConnectionLimits {
+        ConnectionLimits::default()
+            .with_max_pending_incoming(self.max_pending_incoming)
+            .with_max_pending_outgoing(self.max_pending_outgoing)
+            .with_max_established_incoming(self.max_established_incoming)
+            .with_max_established_outgoing(self.max_established_outgoing)
+            .with_max_established(self.max_established)
+            .with_max_established_per_peer(self.max_established_per_peer)
+    }
+}
+
+/// The gossipsub settings of the node, defaults to the libp2p gossipsub defaults
+#[derive(Clone, Debug)]
+pub struct GossipsubSettings {
+    /// Target number of peers in the mesh of a topic (D)
+    pub mesh_n: usize,
+    /// Minimum number of peers in the mesh of a topic before grafting new peers (D_lo)
+    pub mesh_n_low: usize,
+    /// Maximum number of peers in the mesh of a topic before pruning peers (D_hi)
+    pub mesh_n_high: usize,
+    /// Minimum number of peers outside the mesh to gossip to at each heartbeat (D_lazy)
+    pub gossip_lazy: usize,
+    /// Time between each heartbeat
+    pub heartbeat_interval: Duration,
+    /// Maximum size of a gossipsub message, in bytes
+    pub max_transmit_size: usize,
+    /// Number of heartbeats to keep in the message cache
+    pub history_length: usize,
+    /// Number of past heartbeats to gossip about
+    pub history_gossip: usize,
+    /// Publish messages to all known peers of a topic, not only to the mesh
+    pub flood_publish: bool,
+    /// Time to keep message ids in the duplicate cache
+    pub duplicate_cache_time: Duration,
+    /// How the signature and the source of the received messages are checked
+    pub validation_mode: ValidationMode,
+}
+
+impl Default for GossipsubSettings {
+    fn default() -> Self {
+        Self {
+            mesh_n: 6,
+            mesh_n_low: 5,
+            mesh_n_high: 12,
+            gossip_lazy: 6,
+            heartbeat_interval: Duration::from_secs(1),
+            max_transmit_size: 65536,
+            history_length: 5,
+            history_gossip: 3,
+            flood_publish: true,
+            duplicate_cache_time: Duration::from_secs(60),
+            validation_mode: ValidationMode::Strict,
+        }
+    }
+}
+
+impl GossipsubSettings {
+    /// Preset for small and frequent messages such as price updates: denser mesh and faster heartbeat
+    pub fn low_latency() -> Self {
+        Self {
+            mesh_n: 8,
+            mesh_n_low: 6,
+            mesh_n_high: 12,
+            heartbeat_interval: Duration::from_millis(300),
+            history_length: 6,
+            history_gossip: 3,
+            flood_publish: true,
+            ..Default::default()
+        }
+    }
+    /// Preset for large messages such as snapshots: 4 MiB messages, sparser mesh and longer duplicate cache
+    pub fn large_payload() -> Self {
+        Self {
+            mesh_n: 4,
+            mesh_n_low: 3,
+            mesh_n_high: 8,
+            max_transmit_size: 4 * 1024 * 1024,
+            flood_publish: false,
+            duplicate_cache_time: Duration::from_secs(120),
+            ..Default::default()
+        }
+    }
+    /// Build the libp2p gossipsub configuration, messages are only propagated once validated by the node
+    pub(crate) fn to_config(
+        &self,
+        message_id: &MessageIdStrategy,
+    ) -> anyhow::Result<libp2p_gossipsub::Config> {
+        let mut cfg = libp2p_gossipsub::ConfigBuilder::default();
+        cfg.mesh_n(self.mesh_n)
+            .mesh_n_low(self.mesh_n_low)
+            .mesh_n_high(self.mesh_n_high)
+            .gossip_lazy(self.gossip_lazy)
+            .heartbeat_interval(self.heartbeat_interval)
+            .max_transmit_size(self.max_transmit_size)
+            .history_length(self.history_length)
+            .history_gossip(self.history_gossip)
+            .flood_publish(self.flood_publish)
+            .duplicate_cache_time(self.duplicate_cache_time)
+            .validation_mode(self.validation_mode.clone());
+        match message_id {
+            MessageIdStrategy::SourceSequence => {}
+            MessageIdStrategy::ContentHash => {
+                cfg.message_id_fn(content_hash_message_id);
+            }
+            MessageIdStrategy::Custom(id_fn) => {
+                let id_fn = id_fn.clone();
+                cfg.message_id_fn(move |message| id_fn(message));
+            }
+        }
+        cfg.validate_messages();
+        cfg.build()
+            .map_err(|err| anyhow::anyhow!("Error making gossipsub config: {err}"))
+    }
+}
+
+/// A function computing the id of a gossipsub message
+pub type MessageIdFn = Arc<dyn Fn(&Message) -> MessageId + Send + Sync>;
+
+/// How the gossipsub message ids are computed, messages with the same id are only delivered once
+#[derive(Clone, Default)]
+pub enum MessageIdStrategy {
+    /// The libp2p default: source peer id and sequence number, the same data published by two nodes has two ids
+    #[default]
+    SourceSequence,
+    /// SHA-256 of the topic and the data, the same data published by two nodes has the same id
+    ContentHash,
+    /// A user defined function
+    Custom(MessageIdFn),
+}
+
+impl fmt::Debug for MessageIdStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SourceSequence => write!(f, "SourceSequence"),
+            Self::ContentHash => write!(f, "ContentHash"),
+            Self::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+/// The SHA-256 of the topic and the data of a message
+fn content_hash_message_id(message: &Message) -> MessageId {
+    let mut hasher = Sha256::new();
+    hasher.update(message.topic.as_str().as_bytes());
+    hasher.update(&message.data);
+    MessageId::new(&hasher.finalize())
+}
+
+/// The gossipsub peer scoring settings: global parameters, thresholds and per topic parameters
+#[derive(Clone, Debug, Default)]
+pub struct PeerScoreSettings {
+    /// The global score parameters, the topics parameters are taken from `topic_params`
+    pub params: PeerScoreParams,
+    /// The score thresholds, peers below the graylist threshold are ignored
+    pub thresholds: PeerScoreThresholds,
+    /// The score parameters of each topic, by topic name
+    pub topic_params: HashMap<String, TopicScoreParams>,
+}
+
+impl PeerScoreSettings {
+    /// Define the score parameters of a topic, by name
+    pub fn with_topic_params(mut self, topic: impl Into<String>, params: TopicScoreParams) -> Self {
+        self.topic_params.insert(topic.into(), params);
+        self
+    }
+    /// The global score parameters, with the topics parameters indexed by topic hash
+    pub(crate) fn to_params(&self) -> PeerScoreParams {
+        let mut params = self.params.clone();
+        for (topic, topic_params) in &self.topic_params {
+            params
+                .topics
+                .insert(IdentTopic::new(topic).hash(), topic_params.clone());
+        }
+        params
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn routable(settings: AddressSettings, address: &str) -> bool {
+        settings.is_routable(&address.parse().unwrap())
+    }
+
+    #[test]
+    fn filter_non_routable_addresses() {
+        let settings = AddressSettings::default();
+        assert!(routable(settings, "/ip4/8.8.8.8/tcp/1123"));
+        assert!(routable(settings, "/ip6/2001:4860::8888/udp/1123/quic-v1"));
+        assert!(!routable(settings, "/ip4/127.0.0.1/tcp/1123"));
+        assert!(!routable(settings, "/ip6/::1/tcp/1123"));
+        assert!(!routable(settings, "/ip4/192.168.1.10/tcp/1123"));
+        assert!(!routable(settings, "/ip4/10.0.0.1/tcp/1123"));
+        assert!(!routable(settings, "/ip4/169.254.1.1/tcp/1123"));
+        assert!(!routable(settings, "/ip6/fe80::1/tcp/1123"));
+        assert!(!routable(settings, "/ip6/fd00::1/tcp/1123"));
+        assert!(!routable(settings, "/ip4/0.0.0.0/tcp/1123"));
+        assert!(!routable(settings, "/ip6/::/tcp/1123"));
+        assert!(routable(settings, "/dns4/node.example.com/tcp/1123"));
+        assert!(routable(settings, "/ip4/8.8.8.8/tcp/1124/ws"));
+        assert!(!routable(settings, "/ip4/127.0.0.1/tcp/1124/ws"));
+    }
+
+    #[test]
+    fn keep_allowed_local_addresses() {
+        let settings = AddressSettings {
+            allow_private: true,
+            allow_loopback: true,
+            ..Default::default()
+        };
+        assert!(routable(settings, "/ip4/127.0.0.1/tcp/1123"));
+        assert!(routable(settings, "/ip6/::1/tcp/1123"));
+        assert!(routable(settings, "/ip4/192.168.1.10/tcp/1123"));
+        assert!(routable(settings, "/ip4/169.254.1.1/tcp/1123"));
+        assert!(routable(settings, "/ip6/fd00::1/tcp/1123"));
+        assert!(routable(settings, "/ip6/fe80::1/tcp/1123"));
+        assert!(!routable(settings, "/ip4/0.0.0.0/tcp/1123"));
+        assert!(!routable(settings, "/ip6/::/tcp/1123"));
+    }
+
+    #[test]
+    fn rank_addresses_by_transport() {
+        let tcp: Multiaddr = "/ip4/8.8.8.8/tcp/1123".parse().unwrap();
+        let quic: Multiaddr = "/ip4/8.8.8.8/udp/1123/quic-v1".parse().unwrap();
+        let priorities = |preference: TransportPreference| {
+            (preference.priority(&tcp), preference.priority(&quic))
+        };
+        assert_eq!(priorities(TransportPreference::TcpOnly), (Some(0), None));
+        assert_eq!(priorities(TransportPreference::QuicOnly), (None, Some(0)));
+        assert_eq!(
+            priorities(TransportPreference::PreferQuic),
+            (Some(1), Some(0))
+        );
+        assert_eq!(
+            priorities(TransportPreference::PreferTcp),
+            (Some(0), Some(1))
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use crate::{
+    P2pNode,
+    error::{DhtError, P2pError},
+    types::Responder,
+};
+use libp2p::{
+    PeerId,
+    kad::{self, QueryId, QueryResult, QueryStats},
+};
+use std::{
+    collections::HashSet,
+    num::NonZeroUsize,
+    time::{Duration, Instant},
+};
+
+/// The result of a DHT query, with the quorum information
+#[derive(Clone, Debug)]
+pub struct DhtQueryResult<T> {
+    pub value: T,
+    pub quorum: QueryQuorum,
+}
+
+/// How many peers answered a DHT query, and how many were required
+#[derive(Clone, Copy, Debug)]
+pub struct QueryQuorum {
+    /// The number of peers required for the query to succeed, None if the query has no quorum
+    pub required: Option<usize>,
+    /// The number of peers that returned the record, are providers or are the closest peers.
+    /// None when kademlia does not report it: storing a record or announcing a provider
+    pub reached: Option<usize>,
+    /// The number of requests sent during the query
+    pub requests: u32,
+    /// The number of successful requests
+    pub successes: u32,
+    /// The number of failed requests
+    pub failures: u32,
+    /// The duration of the query
+    pub duration: Option<Duration>,
+}
+
+impl QueryQuorum {
+    fn new(required: Option<NonZeroUsize>, reached: Option<usize>, stats: &QueryStats) -> Self {
+        Self {
+            required: required.map(NonZeroUsize::get),
+            reached,
+            requests: stats.num_requests(),
+            successes: stats.num_successes(),
+            failures: stats.num_failures(),
+            duration: stats.duration(),
+        }
+    }
+}
+
+/// A record found in the DHT
+#[derive(Clone, Debug)]
+pub struct DhtRecord {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    /// The peer that published the record
+    pub publisher: Option<PeerId>,
+    /// The peer the record was received from, None if it was found in the local store
+    pub peer: Option<PeerId>,
+    /// When the record expires
+    pub expires: Option<Instant>,
+}
+
+impl From<kad::PeerRecord> for DhtRecord {
+    fn from(record: kad::PeerRecord) -> Self {
+        Self {
+            key: record.record.key.to_vec(),
+            value: record.record.value,
+            publisher: record.record.publisher,
+            peer: record.peer,
+            expires: record.record.expires,
+        }
+    }
+}
+
+type DhtResponder<T> = Responder<Result<DhtQueryResult<T>, P2pError>>;
+
+/// A DHT query started through the node API, with the results received so far
+pub(crate) enum PendingQuery {
+    PutRecord {
+        quorum: NonZeroUsize,
+        responder: DhtResponder<()>,
+    },
+    GetRecord {
+        quorum: NonZeroUsize,
+        records: Vec<DhtRecord>,
+        responder: DhtResponder<Vec<DhtRecord>>,
+    },
+    StartProviding {
+        responder: DhtResponder<()>,
+    },
+    GetProviders {
+        providers: HashSet<PeerId>,
+        responder: DhtResponder<HashSet<PeerId>>,
+    },
+    FindClosestPeers {
+        responder: DhtResponder<Vec<PeerId>>,
+    },
+}
+
+impl PendingQuery {
+    /// Answer the caller with an error
+    fn fail(self, error: DhtError) {
+        let error = P2pError::from(error);
+        match self {
+            Self::PutRecord { responder, .. } | Self::StartProviding { responder } => {
+                let _ = responder.send(Err(error));
+            }
+            Self::GetRecord { responder, .. } => {
+                let _ = responder.send(Err(error));
+            }
+            Self::GetProviders { responder, .. } => {
+                let _ = responder.send(Err(error));
+            }
+            Self::FindClosestPeers { responder } => {
+                let _ = responder.send(Err(error));
+            }
+        }
+    }
+}
+
+impl P2pNode {
+    /// Track a query started through the node API, the caller is answered at once if the query could not start
+    pub(crate) fn start_query(
+        &mut self,
+        query_id: Result<QueryId, kad::store::Error>,
+        query: PendingQuery,
+    ) {
+        match query_id {
+            Ok(query_id) => {
+                self.pending_queries.insert(query_id, query);
+            }
+            Err(e) => query.fail(DhtError::Store(e)),
+        }
+    }
+    /// Collect the results of a query, the caller is answered once the query is finished
+    pub(crate) fn handle_query_progress(
+        &mut self,
+        query_id: QueryId,
+        result: QueryResult,
+        stats: QueryStats,
+        last: bool,
+    ) {
+        let Some(query) = self.pending_queries.remove(&query_id) else {
+            return;
+        };
+        let query = match (query, result) {
+            (PendingQuery::PutRecord { quorum, responder }, QueryResult::PutRecord(result)) => {
+                let result = match result {
+                    Ok(_) => Ok(DhtQueryResult {
+                        value: (),
+                        quorum: QueryQuorum::new(Some(quorum), None, &stats),
+                    }),
+                    Err(kad::PutRecordError::QuorumFailed { success, .. }) => {
+                        Err(DhtError::QuorumFailed {
+                            required: quorum.get(),
+                            reached: success.len(),
+                        })
+                    }
+                    Err(kad::PutRecordError::Timeout { .. }) => Err(DhtError::Timeout),
+                };
+                let _ = responder.send(result.map_err(Into::into));
+                return;
+            }
+            (
+                PendingQuery::GetRecord {
+                    quorum,
+                    mut records,
+                    responder,
+                },
+                QueryResult::GetRecord(result),
+            ) => {
+                let timeout = matches!(result, Err(kad::GetRecordError::Timeout { .. }));
+                if let Ok(kad::GetRecordOk::FoundRecord(record)) = result {
+                    records.push(record.into());
+                    if records.len() >= quorum.get()
+                        && let Some(mut query) =
+                            self.swarm.behaviour_mut().kademlia.query_mut(&query_id)
+                    {
+                        query.finish();
+                    }
+                }
+                if !last {
+                    PendingQuery::GetRecord {
+                        quorum,
+                        records,
+                        responder,
+                    }
+                } else {
+                    let result = if records.len() >= quorum.get() {
+                        Ok(DhtQueryResult {
+                            quorum: QueryQuorum::new(Some(quorum), Some(records.len()), &stats),
+                            value: records,
+                        })
+                    } else if timeout {
+                        Err(DhtError::Timeout)
+                    } else if records.is_empty() {
+                        Err(DhtError::NotFound)
+                    } else {
+                        Err(DhtError::QuorumFailed {
+                            required: quorum.get(),
+                            reached: records.len(),
+                        })
+                    };
+                    let _ = responder.send(result.map_err(Into::into));
+                    return;
+                }
+            }
+            (PendingQuery::StartProviding { responder }, QueryResult::StartProviding(result)) => {
+                let result = match result {
+                    Ok(_) => Ok(DhtQueryResult {
+                        value: (),
+                        quorum: QueryQuorum::new(None, None, &stats),
+                    }),
+                    Err(kad::AddProviderError::Timeout { .. }) => Err(DhtError::Timeout),
+                };
+                let _ = responder.send(result.map_err(Into::into));
+                return;
+            }
+            (
+                PendingQuery::GetProviders {
+                    mut providers,
+                    responder,
+                },
+                QueryResult::GetProviders(result),
+            ) => {
+                let timeout = result.is_err();
+                if let Ok(kad::GetProvidersOk::FoundProviders {
+                    providers: found, ..
+                }) = result
+                {
+                    providers.extend(found);
+                }
+                if !last {
+                    PendingQuery::GetProviders {
+                        providers,
+                        responder,
+                    }
+                } else {
+                    let result = if providers.is_empty() && timeout {
+                        Err(DhtError::Timeout)
+                    } else {
+                        Ok(DhtQueryResult {
+                            quorum: QueryQuorum::new(None, Some(providers.len()), &stats),
+                            value: providers,
+                        })
+                    };
+                    let _ = responder.send(result.map_err(Into::into));
+                    return;
+                }
+            }
+            (
+                PendingQuery::FindClosestPeers { responder },
+                QueryResult::GetClosestPeers(result),
+            ) => {
+                let result = match result {
+                    Ok(kad::GetClosestPeersOk { peers, .. })
+                    | Err(kad::GetClosestPeersError::Timeout { peers, .. })
+                        if !peers.is_empty() =>
+                    {
+                        let peers: Vec<_> = peers.into_iter().map(|peer| peer.peer_id).collect();
+                        Ok(DhtQueryResult {
+                            quorum: QueryQuorum::new(None, Some(peers.len()), &stats),
+                            value: peers,
+                        })
+                    }
+                    Ok(_) => Ok(DhtQueryResult {
+                        quorum: QueryQuorum::new(None, Some(0), &stats),
+                        value: Vec::new(),
+                    }),
+                    Err(_) => Err(DhtError::Timeout),
+                };
+                let _ = responder.send(result.map_err(Into::into));
+                return;
+            }
+            (_, result) => {
+                tracing::warn!("Unexpected result for DHT query {query_id:?}: {result:?}");
+                return;
+            }
+        };
+        self.pending_queries.insert(query_id, query);
+    }
+}
@@ -0,0 +1,95 @@
+use async_trait::async_trait;
+use libp2p::{
+    StreamProtocol,
+    futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    request_response,
+};
+use std::io;
+
+/// The direct messaging protocol, for point-to-point requests between peers
+pub const DIRECT_PROTOCOL: StreamProtocol = StreamProtocol::new("/pragma/request/1.0.0");
+
+/// Encodes the direct requests and responses as raw bytes, up to a maximum size
+#[derive(Clone, Debug)]
+pub struct DirectCodec {
+    max_message_size: u64,
+}
+
+impl DirectCodec {
+    pub fn new(max_message_size: usize) -> Self {
+        Self {
+            max_message_size: u64::try_from(max_message_size).unwrap_or(u64::MAX),
+        }
+    }
+    async fn read<T>(&self, io: &mut T) -> io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let mut bytes = Vec::new();
+        io.take(self.max_message_size.saturating_add(1))
+            .read_to_end(&mut bytes)
+            .await?;
+        if bytes.len() as u64 > self.max_message_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "direct message too large",
+            ));
+        }
+        Ok(bytes)
+    }
+    async fn write<T>(&self, io: &mut T, bytes: Vec<u8>) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        if bytes.len() as u64 > self.max_message_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "direct message too large",
+            ));
+        }
+        io.write_all(&bytes).await?;
+        io.close().await
+    }
+}
+
+#[async_trait]
+impl request_response::Codec for DirectCodec {
+    type Protocol = StreamProtocol;
+    type Request = Vec<u8>;
+    type Response = Vec<u8>;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        self.read(io).await
+    }
+    async fn read_response<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        self.read(io).await
+    }
+    async fn write_request<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        request: Vec<u8>,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        self.write(io, request).await
+    }
+    async fn write_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        response: Vec<u8>,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        self.write(io, response).await
+    }
+}
@@ -0,0 +1,50 @@
+use libp2p::{TransportError, swarm::DialError};
+use libp2p_gossipsub::{PublishError, SubscriptionError};
+
+/// Errors returned to the callers of a [`crate::handle::P2pHandle`]
+#[derive(thiserror::Error, Debug)]
+pub enum P2pError {
+    #[error("The p2p node is not running")]
+    NodeStopped,
+    #[error("Failed to update topic subscription: {0}")]
+    Subscription(#[from] SubscriptionError),
+    #[error("Failed to send gossipsub message: {0}")]
+    Publish(#[from] PublishError),
+    #[error("Failed to dial peer: {0}")]
+    Dial(#[from] DialError),
+    #[error(transparent)]
+    Encode(#[from] EncodeError),
+    #[error("No allow list is configured on the node")]
+    AllowListDisabled,
+    #[error("Failed to listen on address: {0}")]
+    Listen(#[from] TransportError<std::io::Error>),
+    #[error("The transport of address {0} is disabled on the node")]
+    TransportDisabled(libp2p::Multiaddr),
+    #[error("DHT query failed: {0}")]
+    Dht(#[from] DhtError),
+    #[error("Direct request failed: {0}")]
+    Request(#[from] libp2p::request_response::OutboundFailure),
+}
+
+/// Errors of the DHT queries
+#[derive(thiserror::Error, Debug)]
+pub enum DhtError {
+    #[error("Failed to store the record locally: {0}")]
+    Store(#[from] libp2p::kad::store::Error),
+    #[error("Quorum failed: reached {reached} of {required} peers")]
+    QuorumFailed { required: usize, reached: usize },
+    #[error("The record was not found")]
+    NotFound,
+    #[error("The query timed out")]
+    Timeout,
+}
+
+/// Error returned when a payload cannot be encoded by a topic codec
+#[derive(thiserror::Error, Debug)]
+#[error("Failed to encode payload: {0}")]
+pub struct EncodeError(pub Box<dyn std::error::Error + Send + Sync>);
+
+/// Error returned when a received payload cannot be decoded by a topic codec
+#[derive(thiserror::Error, Debug)]
+#[error("Failed to decode payload: {0}")]
+pub struct DecodeError(pub Box<dyn std::error::Error + Send + Sync>);
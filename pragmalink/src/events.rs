@@ -1,6 +1,25 @@
-use crate::{behavior::P2pBehaviorEvent, types::ReceivedMessage, P2pNode};
-use anyhow::Context;
-use libp2p::{identify, swarm::SwarmEvent};
+use crate::{
+    MESSAGE_VALIDATION_TIMEOUT, P2pNode,
+    behavior::P2pBehaviorEvent,
+    certificate::{CertificateRequest, CertificateResponse, PendingExchange},
+    types::{
+        CertificateState, MessageValidationRequest, P2pEvent, PeerEvent, PeerInfo,
+        ReceivedConnection, ReceivedMessage, ReceivedRequest, RejectionReason,
+    },
+};
+use libp2p::{
+    Multiaddr, PeerId, identify, kad, mdns,
+    request_response::{self, ResponseChannel},
+    swarm::{
+        SwarmEvent,
+        dial_opts::{DialOpts, PeerCondition},
+    },
+};
+use libp2p_gossipsub::{IdentTopic, MessageAcceptance, MessageId, TopicHash};
+use std::{
+    collections::{HashMap, HashSet},
+    time::SystemTime,
+};
 
 impl P2pNode {
     pub async fn handle_swarm_event(
@@ -10,77 +29,284 @@ impl P2pNode {
         match event {
             SwarmEvent::NewListenAddr { address, .. } => {
                 let listen_address = address
+                    .clone()
                     .with_p2p(*self.swarm.local_peer_id())
                     .expect("Making multiaddr");
                 tracing::info!("📡 Peer-to-peer listening on address {listen_address:?}");
+                self.emit_event(P2pEvent::NewListenAddr { address });
+            }
+            SwarmEvent::ExpiredListenAddr { address, .. } => {
+                tracing::info!("Stopped listening on address {address}");
+                self.emit_event(P2pEvent::ExpiredListenAddr { address });
+            }
+            SwarmEvent::ListenerClosed {
+                listener_id,
+                addresses,
+                reason,
+            } => {
+                self.listeners.retain(|_, id| *id != listener_id);
+                match reason {
+                    Ok(()) => tracing::info!("Listener on addresses {addresses:?} closed"),
+                    Err(e) => tracing::error!("Listener on addresses {addresses:?} closed: {e}"),
+                }
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                endpoint,
+                num_established,
+                ..
+            } => {
+                let address = endpoint.get_remote_address().clone();
+                let peer = self.peer_table.entry(peer_id).or_insert_with(|| PeerInfo {
+                    addresses: HashSet::new(),
+                    connections: 0,
+                    connected_since: SystemTime::now(),
+                    certificate: CertificateState::Pending,
+                    agent_version: None,
+                });
+                peer.addresses.insert(address.clone());
+                peer.connections = num_established.get() as usize;
+                if num_established.get() == 1 {
+                    tracing::debug!("Connected to peer {peer_id} on {address}");
+                    self.emit_peer_event(PeerEvent::Connected { peer_id, address });
+                }
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                endpoint,
+                num_established,
+                cause,
+                ..
+            } => {
+                if num_established == 0 {
+                    self.remove_peer(&peer_id);
+                    let cause = cause.map(|e| e.to_string());
+                    tracing::debug!("Disconnected from peer {peer_id}: {cause:?}");
+                    self.emit_peer_event(PeerEvent::Disconnected { peer_id, cause });
+                } else if let Some(peer) = self.peer_table.get_mut(&peer_id) {
+                    peer.addresses.remove(endpoint.get_remote_address());
+                    peer.connections = num_established as usize;
+                }
+            }
+            SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
+                tracing::warn!("Failed to dial peer {peer_id:?}: {error}");
+                self.emit_peer_event(PeerEvent::DialFailed {
+                    peer_id,
+                    error: error.to_string(),
+                });
+            }
+            SwarmEvent::IncomingConnectionError {
+                send_back_addr,
+                error,
+                ..
+            } => {
+                tracing::warn!("Incoming connection from {send_back_addr} failed: {error}");
             }
             SwarmEvent::Behaviour(behaviour) => match behaviour {
                 P2pBehaviorEvent::Gossipsub(libp2p_gossipsub::Event::Message {
-                    message, ..
+                    propagation_source,
+                    message_id,
+                    message,
                 }) => {
                     let topic_name = match self.gossipsub_topics.get(&message.topic) {
                         Some(topic) => topic,
                         None => {
-                            tracing::warn!(
-                                "Received a message on an unsubscribed topic",
+                            tracing::warn!("Received a message on an unsubscribed topic",);
+                            self.report_validation(
+                                &message_id,
+                                &propagation_source,
+                                MessageAcceptance::Ignore,
                             );
-                            return Ok(())
+                            return Ok(());
                         }
                     };
 
                     let received_message = ReceivedMessage {
+                        message_id: message_id.clone(),
                         source: message.source.map(|peer_id| peer_id.to_string()),
+                        propagation_source,
+                        sequence_number: message.sequence_number,
+                        received_at: SystemTime::now(),
+                        source_certified: message
+                            .source
+                            .filter(|source| self.peers.contains(source))
+                            .map(|source| self.certified_peers.contains(&source)),
                         data: message.data.clone(),
                         topic: topic_name.clone(),
                     };
 
-                    self.received_messages_tx
-                        .send(received_message)
-                        .context("fatal error: messages rx send failed")?;
+                    if let Some(decoder) = self.topic_decoders.get(&message.topic)
+                        && let Err(e) = decoder(&message.data)
+                    {
+                        tracing::warn!(
+                            "Rejected message {message_id} from peer {propagation_source} on topic {}: {e}",
+                            received_message.topic
+                        );
+                        self.report_validation(
+                            &message_id,
+                            &propagation_source,
+                            MessageAcceptance::Reject,
+                        );
+                        return Ok(());
+                    }
+
+                    match self.message_validator_tx.clone() {
+                        Some(validator_tx) => self.request_validation(
+                            validator_tx,
+                            message_id,
+                            propagation_source,
+                            received_message,
+                        ),
+                        None => {
+                            self.handle_validation_result(
+                                message_id,
+                                propagation_source,
+                                received_message,
+                                MessageAcceptance::Accept,
+                            )
+                            .await
+                        }
+                    }
+                }
+                P2pBehaviorEvent::Gossipsub(libp2p_gossipsub::Event::Subscribed {
+                    peer_id,
+                    topic,
+                }) => {
+                    let topic = self.topic_name(&topic);
+                    tracing::debug!("Peer {peer_id} subscribed to topic {topic}");
+                    self.emit_event(P2pEvent::TopicPeerSubscribed { peer_id, topic });
+                }
+                P2pBehaviorEvent::Gossipsub(libp2p_gossipsub::Event::Unsubscribed {
+                    peer_id,
+                    topic,
+                }) => {
+                    let topic = self.topic_name(&topic);
+                    tracing::debug!("Peer {peer_id} unsubscribed from topic {topic}");
+                    self.emit_event(P2pEvent::TopicPeerUnsubscribed { peer_id, topic });
                 }
                 P2pBehaviorEvent::Identify(identify::Event::Received {
                     peer_id,
                     info,
                     connection_id,
                 }) => {
-                    let connection_request = identify::Event::Received {
-                        peer_id: peer_id.clone(),
-                        info: info.clone(),
-                        connection_id: connection_id.clone(),
+                    if let Some(peer) = self.peer_table.get_mut(&peer_id) {
+                        peer.agent_version = Some(info.agent_version.clone());
                     }
-                    .try_into();
-                    let authorization_rx = match connection_request {
-                        Ok(request) => {
-                            let (tx, rx) = tokio::sync::oneshot::channel();
-                            self.connection_authorization_tx
-                                .send((request, tx))
-                                .await
-                                .context("fatal error: connection authorization tx send failed")?;
-                            rx
-                        }
+                    let connection_request =
+                        ReceivedConnection::try_from(identify::Event::Received {
+                            peer_id,
+                            info: info.clone(),
+                            connection_id,
+                        });
+                    match connection_request {
+                        Ok(connection) => self.request_certificate(peer_id, connection, info),
                         Err(e) => {
                             tracing::error!(
                                 "Failed to convert identify event to connection request: {}",
                                 e
                             );
-                            return Ok(());
+                            self.reject_peer(
+                                peer_id,
+                                RejectionReason::InvalidIdentity(e.to_string()),
+                            );
+                        }
+                    }
+                }
+                P2pBehaviorEvent::Kademlia(kad::Event::OutboundQueryProgressed {
+                    id,
+                    result,
+                    stats,
+                    step,
+                }) => self.handle_query_progress(id, result, stats, step.last),
+                P2pBehaviorEvent::DirectMessages(request_response::Event::Message {
+                    peer,
+                    message,
+                }) => match message {
+                    request_response::Message::Request {
+                        request, channel, ..
+                    } => self.handle_direct_request(peer, request, channel),
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    } => {
+                        if let Some(responder) = self.pending_requests.remove(&request_id) {
+                            let _ = responder.send(Ok(response));
+                        }
+                    }
+                },
+                P2pBehaviorEvent::DirectMessages(request_response::Event::OutboundFailure {
+                    peer,
+                    request_id,
+                    error,
+                }) => {
+                    tracing::debug!("Direct request to peer {peer} failed: {error}");
+                    if let Some(responder) = self.pending_requests.remove(&request_id) {
+                        let _ = responder.send(Err(error.into()));
+                    }
+                }
+                P2pBehaviorEvent::Mdns(mdns::Event::Discovered(discovered)) => {
+                    self.handle_mdns_discovered(discovered);
+                }
+                P2pBehaviorEvent::Mdns(mdns::Event::Expired(expired)) => {
+                    for (peer_id, address) in expired {
+                        tracing::debug!("mDNS address {address} of peer {peer_id} expired");
+                        if let Some(addresses) = self.mdns_addresses.get_mut(&peer_id) {
+                            addresses.remove(&address);
+                            if addresses.is_empty() {
+                                self.mdns_addresses.remove(&peer_id);
+                            }
                         }
-                    };
-
-                    if authorization_rx
-                        .await
-                        .context("fatal error: connection authorization rx receive failed")?
-                    {
-                        self.swarm
-                            .behaviour_mut()
-                            .gossipsub
-                            .add_explicit_peer(&peer_id);
-                        self.peers.insert(peer_id);
                         self.swarm
                             .behaviour_mut()
                             .kademlia
-                            .add_address(&peer_id, info.observed_addr);
-                        tracing::info!("🤝 Peer {peer_id} accepted and added in kademlia peers");
+                            .remove_address(&peer_id, &address);
+                    }
+                }
+                P2pBehaviorEvent::CertificateExchange(request_response::Event::Message {
+                    peer,
+                    message,
+                }) => match message {
+                    request_response::Message::Request {
+                        request, channel, ..
+                    } => {
+                        match CertificateResponse::new(
+                            &self.keypair,
+                            &request,
+                            &peer,
+                            self.certificate.clone(),
+                        ) {
+                            Ok(response) => {
+                                if self
+                                    .swarm
+                                    .behaviour_mut()
+                                    .certificate_exchange
+                                    .send_response(channel, response)
+                                    .is_err()
+                                {
+                                    tracing::debug!("Peer {peer} closed the certificate exchange");
+                                }
+                            }
+                            Err(e) => tracing::error!("Failed to sign certificate challenge: {e}"),
+                        }
+                    }
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    } => self.handle_certificate_response(peer, request_id, response),
+                },
+                P2pBehaviorEvent::CertificateExchange(
+                    request_response::Event::OutboundFailure {
+                        peer,
+                        request_id,
+                        error,
+                    },
+                ) => {
+                    let exchange = self.take_pending_exchange(&peer, request_id);
+                    if exchange.is_some() {
+                        self.reject_peer(
+                            peer,
+                            RejectionReason::CertificateExchangeFailed(error.to_string()),
+                        );
                     }
                 }
                 _ => {}
@@ -89,4 +315,382 @@ impl P2pNode {
         }
         Ok(())
     }
+    /// Challenge an identified peer to send its certificate, it is authorized once the response is received.
+    /// Identify runs periodically, an authorized peer is not challenged again
+    fn request_certificate(
+        &mut self,
+        peer_id: PeerId,
+        connection: ReceivedConnection,
+        info: identify::Info,
+    ) {
+        if self.peers.contains(&peer_id) {
+            return;
+        }
+        if self.pending_exchanges.contains_key(&peer_id)
+            || self.authorizing_peers.contains(&peer_id)
+        {
+            tracing::debug!("Authorization of peer {peer_id} already pending");
+            return;
+        }
+        let request = CertificateRequest::random();
+        let request_id = self
+            .swarm
+            .behaviour_mut()
+            .certificate_exchange
+            .send_request(&peer_id, request.clone());
+        self.pending_exchanges.insert(
+            peer_id,
+            PendingExchange {
+                request_id,
+                request,
+                connection,
+                info,
+            },
+        );
+    }
+    /// The pending certificate exchange of a peer, if it matches the request
+    fn take_pending_exchange(
+        &mut self,
+        peer_id: &PeerId,
+        request_id: request_response::OutboundRequestId,
+    ) -> Option<PendingExchange> {
+        if self
+            .pending_exchanges
+            .get(peer_id)
+            .is_none_or(|exchange| exchange.request_id != request_id)
+        {
+            return None;
+        }
+        self.pending_exchanges.remove(peer_id)
+    }
+    /// Verify the challenge signature of a certificate response, then authorize the peer with its certificate
+    /// and the key it proved to control
+    fn handle_certificate_response(
+        &mut self,
+        peer_id: PeerId,
+        request_id: request_response::OutboundRequestId,
+        response: CertificateResponse,
+    ) {
+        let Some(mut exchange) = self.take_pending_exchange(&peer_id, request_id) else {
+            tracing::debug!("Unexpected certificate response from peer {peer_id}");
+            return;
+        };
+        let public_key = response
+            .verify(
+                &exchange.request,
+                &self.peer_id,
+                &peer_id,
+                &exchange.info.public_key,
+            )
+            .and_then(|public_key| Ok(public_key.try_into_ed25519()?.to_bytes()));
+        let public_key = match public_key {
+            Ok(public_key) => public_key,
+            Err(e) => {
+                tracing::error!("Invalid certificate response from peer {peer_id}: {e}");
+                return self.reject_peer(peer_id, RejectionReason::InvalidIdentity(e.to_string()));
+            }
+        };
+        exchange.connection.pubkey = public_key;
+        exchange.connection.certificate = response.certificate;
+        self.request_authorization(exchange.connection, peer_id, exchange.info);
+    }
+    /// Send a connection authorization request, the verdict is awaited outside of the swarm event loop.
+    /// A peer already waiting for a verdict is not authorized twice
+    fn request_authorization(
+        &mut self,
+        request: ReceivedConnection,
+        peer_id: PeerId,
+        info: identify::Info,
+    ) {
+        if !self.authorizing_peers.insert(peer_id) {
+            tracing::debug!("Authorization of peer {peer_id} already pending");
+            return;
+        }
+        let certified = request.certificate.is_some();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let rx = match self.connection_authorization_tx.try_send((request, tx)) {
+            Ok(()) => Some(rx),
+            Err(e) => {
+                tracing::warn!("Could not send authorization request of peer {peer_id}: {e}");
+                None
+            }
+        };
+        let timeout = self.authorization_settings.timeout;
+        self.pending_authorizations.push(Box::pin(async move {
+            let verdict = match rx {
+                Some(rx) => tokio::time::timeout(timeout, rx)
+                    .await
+                    .ok()
+                    .and_then(Result::ok),
+                None => None,
+            };
+            (peer_id, info, certified, verdict)
+        }));
+    }
+    /// Accept or reject a peer, applying the default verdict if no verdict was received in time
+    pub(crate) fn handle_authorization_result(
+        &mut self,
+        peer_id: PeerId,
+        info: identify::Info,
+        certified: bool,
+        verdict: Option<bool>,
+    ) {
+        self.authorizing_peers.remove(&peer_id);
+        if !self.peer_table.contains_key(&peer_id) {
+            tracing::debug!("Peer {peer_id} disconnected before its authorization");
+            return;
+        }
+        match verdict {
+            Some(true) => {}
+            Some(false) => return self.reject_peer(peer_id, RejectionReason::NotAuthorized),
+            None if self.authorization_settings.allow_on_timeout => {
+                tracing::warn!("No authorization verdict for peer {peer_id}, accepted by default");
+            }
+            None => return self.reject_peer(peer_id, RejectionReason::AuthorizationTimeout),
+        }
+        self.peers.insert(peer_id);
+        if certified {
+            self.certified_peers.insert(peer_id);
+        }
+        if let Some(peer) = self.peer_table.get_mut(&peer_id) {
+            peer.certificate = if certified {
+                CertificateState::Certified
+            } else {
+                CertificateState::Uncertified
+            };
+        }
+        let addresses: Vec<_> = info
+            .listen_addrs
+            .into_iter()
+            .filter(|address| self.address_settings.is_routable(address))
+            .collect();
+        if addresses.is_empty() {
+            tracing::debug!("Peer {peer_id} advertised no routable listen address");
+        }
+        let mdns_addresses = self.mdns_addresses.get(&peer_id).into_iter().flatten();
+        for address in addresses.into_iter().chain(mdns_addresses.cloned()) {
+            self.swarm
+                .behaviour_mut()
+                .kademlia
+                .add_address(&peer_id, address);
+        }
+        self.observe_address(peer_id, info.observed_addr);
+        tracing::info!("🤝 Peer {peer_id} accepted and added in kademlia peers");
+        self.emit_peer_event(PeerEvent::Authorized { peer_id, certified });
+    }
+    /// Disconnect a rejected peer and remove it from kademlia, it is also blocked and blacklisted in gossipsub
+    /// if the rejection is permanent. A temporarily rejected peer can reconnect and be authorized again
+    pub(crate) fn reject_peer(&mut self, peer_id: PeerId, reason: RejectionReason) {
+        tracing::warn!("⛔ Peer {peer_id} rejected: {reason}");
+        self.peers.remove(&peer_id);
+        self.certified_peers.remove(&peer_id);
+        let behaviour = self.swarm.behaviour_mut();
+        behaviour.kademlia.remove_peer(&peer_id);
+        if reason.is_permanent() {
+            behaviour.blocked_peers.block_peer(peer_id);
+            behaviour.gossipsub.blacklist_peer(&peer_id);
+        } else if self.swarm.disconnect_peer_id(peer_id).is_err() {
+            tracing::debug!("Peer {peer_id} already disconnected");
+        }
+        self.emit_peer_event(PeerEvent::Rejected {
+            peer_id,
+            reason: reason.clone(),
+        });
+        self.emit_event(P2pEvent::PeerRejected { peer_id, reason });
+    }
+    /// Dial the peers discovered with mDNS, they go through the certificate exchange and the authorization once identified
+    fn handle_mdns_discovered(&mut self, discovered: Vec<(PeerId, Multiaddr)>) {
+        let mut discovered_peers: HashMap<PeerId, Vec<Multiaddr>> = HashMap::new();
+        for (peer_id, address) in discovered {
+            if self.transport_preference.priority(&address).is_none() {
+                continue;
+            }
+            tracing::debug!("mDNS discovered peer {peer_id} on {address}");
+            self.mdns_addresses
+                .entry(peer_id)
+                .or_default()
+                .insert(address.clone());
+            discovered_peers.entry(peer_id).or_default().push(address);
+        }
+        for (peer_id, mut addresses) in discovered_peers {
+            addresses.sort_by_key(|address| self.transport_preference.priority(address));
+            let opts = DialOpts::peer_id(peer_id)
+                .addresses(addresses)
+                .condition(PeerCondition::DisconnectedAndNotDialing)
+                .build();
+            if let Err(e) = self.swarm.dial(opts) {
+                tracing::debug!("Could not dial mDNS peer {peer_id}: {e}");
+            }
+        }
+    }
+    /// Send a direct request of an authorized peer to the handler, the response is awaited outside of the swarm event loop
+    fn handle_direct_request(
+        &mut self,
+        peer_id: PeerId,
+        data: Vec<u8>,
+        channel: ResponseChannel<Vec<u8>>,
+    ) {
+        if !self.peers.contains(&peer_id) {
+            tracing::debug!("Dropping direct request of unauthorized peer {peer_id}");
+            return;
+        }
+        let Some(request_handler_tx) = &self.request_handler_tx else {
+            tracing::debug!("No request handler, dropping direct request of peer {peer_id}");
+            return;
+        };
+        let request = ReceivedRequest {
+            peer_id,
+            certified: self.certified_peers.contains(&peer_id),
+            data,
+        };
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        if let Err(e) = request_handler_tx.try_send((request, tx)) {
+            tracing::warn!("Could not send direct request of peer {peer_id} to the handler: {e}");
+            return;
+        }
+        let timeout = self.handler_timeout;
+        self.pending_responses.push(Box::pin(async move {
+            let response = tokio::time::timeout(timeout, rx)
+                .await
+                .ok()
+                .and_then(Result::ok);
+            (channel, response)
+        }));
+    }
+    /// Send the handler response of a direct request, the request fails on the peer side if the handler did not answer
+    pub(crate) fn handle_request_response(
+        &mut self,
+        channel: ResponseChannel<Vec<u8>>,
+        response: Option<Vec<u8>>,
+    ) {
+        let Some(response) = response else {
+            tracing::warn!("The request handler did not answer a direct request in time");
+            return;
+        };
+        if self
+            .swarm
+            .behaviour_mut()
+            .direct_messages
+            .send_response(channel, response)
+            .is_err()
+        {
+            tracing::debug!("The peer closed the direct request before the response");
+        }
+    }
+    /// Count an address of the node observed by an authorized peer, confirmed as external once a quorum of peers observed it
+    fn observe_address(&mut self, peer_id: PeerId, address: Multiaddr) {
+        if !self.address_settings.is_routable(&address) {
+            return;
+        }
+        let observers = self.observed_addresses.entry(address.clone()).or_default();
+        if observers.insert(peer_id)
+            && observers.len() == self.address_settings.external_address_quorum.max(1)
+        {
+            tracing::info!(
+                "🌐 External address {address} confirmed by {} peers",
+                observers.len()
+            );
+            self.swarm.add_external_address(address);
+        }
+    }
+    /// Forget a disconnected peer
+    fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.peer_table.remove(peer_id);
+        self.peers.remove(peer_id);
+        self.certified_peers.remove(peer_id);
+        self.pending_exchanges.remove(peer_id);
+        self.graylisted_peers.remove(peer_id);
+        self.observed_addresses.retain(|_, observers| {
+            observers.remove(peer_id);
+            !observers.is_empty()
+        });
+    }
+    /// The name of a topic, falling back to the hash for topics the node is not subscribed to
+    pub(crate) fn topic_name(&self, topic: &TopicHash) -> String {
+        self.gossipsub_topics
+            .get(topic)
+            .cloned()
+            .unwrap_or_else(|| topic.to_string())
+    }
+    /// Send a received message to the validator, the verdict is awaited outside of the swarm event loop
+    fn request_validation(
+        &mut self,
+        validator_tx: tokio::sync::mpsc::Sender<MessageValidationRequest>,
+        message_id: MessageId,
+        propagation_source: PeerId,
+        message: ReceivedMessage,
+    ) {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        if let Err(e) = validator_tx.try_send((message.clone(), tx)) {
+            tracing::warn!("Could not send message for validation, ignoring it: {}", e);
+            self.report_validation(&message_id, &propagation_source, MessageAcceptance::Ignore);
+            return;
+        }
+        self.pending_validations.push(Box::pin(async move {
+            let acceptance = match tokio::time::timeout(MESSAGE_VALIDATION_TIMEOUT, rx).await {
+                Ok(Ok(acceptance)) => acceptance,
+                _ => {
+                    tracing::warn!("No validation verdict for message {message_id}, ignoring it");
+                    MessageAcceptance::Ignore
+                }
+            };
+            (message_id, propagation_source, message, acceptance)
+        }));
+    }
+    /// Report the validator verdict to gossipsub, delivering the message if accepted
+    pub(crate) async fn handle_validation_result(
+        &mut self,
+        message_id: MessageId,
+        propagation_source: PeerId,
+        message: ReceivedMessage,
+        acceptance: MessageAcceptance,
+    ) {
+        let accepted = matches!(acceptance, MessageAcceptance::Accept);
+        if matches!(acceptance, MessageAcceptance::Reject) {
+            tracing::warn!("Rejected message {message_id} propagated by peer {propagation_source}");
+        }
+        self.report_validation(&message_id, &propagation_source, acceptance);
+        if accepted {
+            self.deliver_message(message).await;
+        }
+    }
+    fn report_validation(
+        &mut self,
+        message_id: &MessageId,
+        propagation_source: &PeerId,
+        acceptance: MessageAcceptance,
+    ) {
+        if let Err(e) = self
+            .swarm
+            .behaviour_mut()
+            .gossipsub
+            .report_message_validation_result(message_id, propagation_source, acceptance)
+        {
+            tracing::error!("Failed to report validation result of message {message_id}: {e}");
+        }
+    }
+    /// Send a message to the receivers of its topic, then to the receivers of all the messages
+    async fn deliver_message(&mut self, message: ReceivedMessage) {
+        let topic_hash = IdentTopic::new(&message.topic).hash();
+        if let Some(senders) = self.topic_senders.get_mut(&topic_hash) {
+            senders.retain(|sender| !sender.is_closed());
+            let counters = self.topic_counters.entry(topic_hash).or_default();
+            for sender in senders.iter() {
+                sender.send(message.clone(), counters).await;
+            }
+        }
+        if self.received_messages_tx.send(message).is_err() {
+            tracing::debug!("No receiver for the received message, dropping it");
+        }
+    }
+    pub(crate) fn emit_event(&self, event: P2pEvent) {
+        if self.events_tx.send(event).is_err() {
+            tracing::debug!("No receiver for the node event, dropping it");
+        }
+    }
+    fn emit_peer_event(&self, event: PeerEvent) {
+        if self.peer_events_tx.send(event).is_err() {
+            tracing::debug!("No receiver for the peer event, dropping it");
+        }
+    }
 }
@@ -0,0 +1,23 @@
+use std::io;
+
+/// Read a length prefixed field, advancing the bytes past it
+pub(crate) fn read_field(bytes: &mut &[u8]) -> io::Result<Vec<u8>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "truncated field");
+    let (len, rest) = bytes.split_first_chunk::<4>().ok_or_else(invalid)?;
+    let len = u32::from_be_bytes(*len) as usize;
+    if rest.len() < len {
+        return Err(invalid());
+    }
+    let (field, rest) = rest.split_at(len);
+    *bytes = rest;
+    Ok(field.to_vec())
+}
+
+/// Write a field prefixed with its length
+pub(crate) fn write_field(bytes: &mut Vec<u8>, field: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(field.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "field too large"))?;
+    bytes.extend_from_slice(&len.to_be_bytes());
+    bytes.extend_from_slice(field);
+    Ok(())
+}
@@ -0,0 +1,258 @@
+use crate::{
+    bootstrap::BootstrapStatus,
+    config::ConnectionLimitsSettings,
+    dht::{DhtQueryResult, DhtRecord},
+    error::{DecodeError, P2pError},
+    subscription::{SubscriptionSettings, TopicMetrics, TopicReceiver},
+    topic::{Topic, Typed},
+    types::{P2pEvent, P2pRequest, PeerEvent, PeerInfo, ReceivedMessage},
+};
+use libp2p::{
+    Multiaddr, PeerId,
+    futures::{Stream, stream},
+};
+use libp2p_gossipsub::MessageId;
+use std::{
+    collections::{HashMap, HashSet},
+    num::NonZeroUsize,
+};
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+/// A cloneable handle to a running [`crate::P2pNode`], every call is forwarded to the node event loop
+#[derive(Clone)]
+pub struct P2pHandle {
+    requests_tx: mpsc::Sender<P2pRequest>,
+    received_messages_tx: broadcast::Sender<ReceivedMessage>,
+    events_tx: broadcast::Sender<P2pEvent>,
+    peer_events_tx: broadcast::Sender<PeerEvent>,
+}
+
+impl P2pHandle {
+    pub(crate) fn new(
+        requests_tx: mpsc::Sender<P2pRequest>,
+        received_messages_tx: broadcast::Sender<ReceivedMessage>,
+        events_tx: broadcast::Sender<P2pEvent>,
+        peer_events_tx: broadcast::Sender<PeerEvent>,
+    ) -> Self {
+        Self {
+            requests_tx,
+            received_messages_tx,
+            events_tx,
+            peer_events_tx,
+        }
+    }
+    /// Send a raw request to the node, without waiting for a reply
+    pub async fn send(&self, req: P2pRequest) -> Result<(), P2pError> {
+        self.requests_tx
+            .send(req)
+            .await
+            .map_err(|_| P2pError::NodeStopped)
+    }
+    /// Send a request built around a oneshot responder and wait for the node reply
+    async fn request<T>(
+        &self,
+        req: impl FnOnce(oneshot::Sender<T>) -> P2pRequest,
+    ) -> Result<T, P2pError> {
+        let (tx, rx) = oneshot::channel();
+        self.send(req(tx)).await?;
+        rx.await.map_err(|_| P2pError::NodeStopped)
+    }
+    /// Publish a message on a gossipsub topic, returning the id of the published message
+    pub async fn publish(
+        &self,
+        topic: impl Into<String>,
+        data: Vec<u8>,
+    ) -> Result<MessageId, P2pError> {
+        let topic = topic.into();
+        self.request(|tx| P2pRequest::Broadcast(topic, data, Some(tx)))
+            .await?
+    }
+    /// Subscribe to a gossipsub topic of a given name, returning a dedicated receiver of the topic messages
+    pub async fn subscribe(&self, topic: impl Into<String>) -> Result<TopicReceiver, P2pError> {
+        self.subscribe_with(topic, SubscriptionSettings::default())
+            .await
+    }
+    /// Subscribe to a gossipsub topic, with the capacity and the overflow policy of the returned receiver
+    pub async fn subscribe_with(
+        &self,
+        topic: impl Into<String>,
+        settings: SubscriptionSettings,
+    ) -> Result<TopicReceiver, P2pError> {
+        let topic = topic.into();
+        self.request(|tx| P2pRequest::Subscribe(topic, settings, None, tx))
+            .await?
+    }
+    /// Unsubscribe from a gossipsub topic, returns false if the node was not subscribed
+    pub async fn unsubscribe(&self, topic: impl Into<String>) -> Result<bool, P2pError> {
+        let topic = topic.into();
+        self.request(|tx| P2pRequest::Unsubscribe(topic, tx))
+            .await?
+    }
+    /// Publish a value on a typed topic, encoded with the topic codec
+    pub async fn publish_typed<T: 'static>(
+        &self,
+        topic: &Topic<T>,
+        value: T,
+    ) -> Result<MessageId, P2pError> {
+        let data = topic.encode(&value)?;
+        self.publish(topic.name(), data).await
+    }
+    /// Subscribe to a typed topic, returning a stream of the decoded values.
+    /// The node rejects the messages that cannot be decoded, so they are not propagated
+    pub async fn subscribe_typed<T: Send + 'static>(
+        &self,
+        topic: &Topic<T>,
+    ) -> Result<impl Stream<Item = Result<Typed<T>, DecodeError>> + Send + 'static, P2pError> {
+        let (name, decoder) = (topic.name().to_string(), topic.decoder());
+        let receiver = self
+            .request(|tx| {
+                P2pRequest::Subscribe(name, SubscriptionSettings::default(), Some(decoder), tx)
+            })
+            .await??;
+        let topic = topic.clone();
+        Ok(stream::unfold(receiver, move |mut receiver| {
+            let topic = topic.clone();
+            async move {
+                let message = receiver.recv().await?;
+                let item = topic
+                    .decode(&message.data)
+                    .map(|value| Typed { value, message });
+                Some((item, receiver))
+            }
+        }))
+    }
+    /// The delivery metrics of each topic with dedicated receivers, by topic name
+    pub async fn topic_metrics(&self) -> Result<HashMap<String, TopicMetrics>, P2pError> {
+        self.request(P2pRequest::TopicMetrics).await
+    }
+    /// The peers currently connected to the node
+    pub async fn connected_peers(&self) -> Result<Vec<PeerId>, P2pError> {
+        self.request(P2pRequest::ConnectedPeers).await
+    }
+    /// Store a record in the DHT, succeeds once the record is stored on quorum peers.
+    /// The quorum is capped to the replication factor, the capped quorum is reported in the result
+    pub async fn put_record(
+        &self,
+        key: impl Into<Vec<u8>>,
+        value: Vec<u8>,
+        quorum: NonZeroUsize,
+    ) -> Result<DhtQueryResult<()>, P2pError> {
+        let key = key.into();
+        self.request(|tx| P2pRequest::PutRecord(key, value, quorum, tx))
+            .await?
+    }
+    /// Look up a record in the DHT, succeeds once quorum records are found.
+    /// The quorum is capped to the replication factor, the capped quorum is reported in the result
+    pub async fn get_record(
+        &self,
+        key: impl Into<Vec<u8>>,
+        quorum: NonZeroUsize,
+    ) -> Result<DhtQueryResult<Vec<DhtRecord>>, P2pError> {
+        let key = key.into();
+        self.request(|tx| P2pRequest::GetRecord(key, quorum, tx))
+            .await?
+    }
+    /// Announce the node as a provider of a key
+    pub async fn start_providing(
+        &self,
+        key: impl Into<Vec<u8>>,
+    ) -> Result<DhtQueryResult<()>, P2pError> {
+        let key = key.into();
+        self.request(|tx| P2pRequest::StartProviding(key, tx))
+            .await?
+    }
+    /// Look up the providers of a key
+    pub async fn get_providers(
+        &self,
+        key: impl Into<Vec<u8>>,
+    ) -> Result<DhtQueryResult<HashSet<PeerId>>, P2pError> {
+        let key = key.into();
+        self.request(|tx| P2pRequest::GetProviders(key, tx)).await?
+    }
+    /// Look up the peers closest to a peer id
+    pub async fn find_closest_peers(
+        &self,
+        peer_id: PeerId,
+    ) -> Result<DhtQueryResult<Vec<PeerId>>, P2pError> {
+        self.request(|tx| P2pRequest::FindClosestPeers(peer_id, tx))
+            .await?
+    }
+    /// Send a direct request to a peer, returning its response
+    pub async fn send_request(&self, peer_id: PeerId, data: Vec<u8>) -> Result<Vec<u8>, P2pError> {
+        self.request(|tx| P2pRequest::SendRequest(peer_id, data, tx))
+            .await?
+    }
+    /// The bootstrap state of the node: connected peers and redial attempts
+    pub async fn bootstrap_status(&self) -> Result<BootstrapStatus, P2pError> {
+        self.request(P2pRequest::BootstrapStatus).await
+    }
+    /// The connected peers, with their addresses, connections and certificate state
+    pub async fn peer_table(&self) -> Result<HashMap<PeerId, PeerInfo>, P2pError> {
+        self.request(P2pRequest::PeerTable).await
+    }
+    /// The gossipsub score of the known peers, empty if peer scoring is disabled
+    pub async fn peer_scores(&self) -> Result<HashMap<PeerId, f64>, P2pError> {
+        self.request(P2pRequest::PeerScores).await
+    }
+    /// Dial a peer at a given address
+    pub async fn dial(&self, address: Multiaddr) -> Result<(), P2pError> {
+        self.request(|tx| P2pRequest::Dial(address, tx)).await?
+    }
+    /// The addresses the node is listening on
+    pub async fn listen_addrs(&self) -> Result<Vec<Multiaddr>, P2pError> {
+        self.request(P2pRequest::ListenAddrs).await
+    }
+    /// Listen on a new address, the bound addresses are reported through the node events
+    pub async fn add_listener(&self, address: Multiaddr) -> Result<(), P2pError> {
+        self.request(|tx| P2pRequest::AddListener(address, tx))
+            .await?
+    }
+    /// Stop listening on an address, returns false if the node was not listening on it
+    pub async fn remove_listener(&self, address: Multiaddr) -> Result<bool, P2pError> {
+        self.request(|tx| P2pRequest::RemoveListener(address, tx))
+            .await
+    }
+    /// Block a peer, closing its connections and denying new ones
+    pub async fn block_peer(&self, peer_id: PeerId) -> Result<(), P2pError> {
+        self.request(|tx| P2pRequest::BlockPeer(peer_id, tx)).await
+    }
+    /// Unblock a peer, also removing it from the gossipsub blacklist of rejected peers
+    pub async fn unblock_peer(&self, peer_id: PeerId) -> Result<(), P2pError> {
+        self.request(|tx| P2pRequest::UnblockPeer(peer_id, tx))
+            .await
+    }
+    /// Add a peer to the allow list, fails if the node was built without an allow list
+    pub async fn allow_peer(&self, peer_id: PeerId) -> Result<(), P2pError> {
+        self.request(|tx| P2pRequest::AllowPeer(peer_id, tx))
+            .await?
+    }
+    /// Remove a peer from the allow list, closing its connections
+    pub async fn disallow_peer(&self, peer_id: PeerId) -> Result<(), P2pError> {
+        self.request(|tx| P2pRequest::DisallowPeer(peer_id, tx))
+            .await?
+    }
+    /// Replace the connection limits, existing connections are not closed
+    pub async fn set_connection_limits(
+        &self,
+        limits: ConnectionLimitsSettings,
+    ) -> Result<(), P2pError> {
+        self.request(|tx| P2pRequest::SetConnectionLimits(limits, tx))
+            .await
+    }
+    /// Stop the node event loop, returns once the node acknowledged the shutdown
+    pub async fn shutdown(&self) -> Result<(), P2pError> {
+        self.request(P2pRequest::Shutdown).await
+    }
+    /// A new receiver of all the messages received on the subscribed topics
+    pub fn received_messages(&self) -> broadcast::Receiver<ReceivedMessage> {
+        self.received_messages_tx.subscribe()
+    }
+    /// A new receiver of the node events
+    pub fn events(&self) -> broadcast::Receiver<P2pEvent> {
+        self.events_tx.subscribe()
+    }
+    /// A new receiver of the peer lifecycle events
+    pub fn peer_events(&self) -> broadcast::Receiver<PeerEvent> {
+        self.peer_events_tx.subscribe()
+    }
+}
@@ -1,18 +1,73 @@
 use crate::behavior::P2pBehavior;
+use crate::bootstrap::BootstrapManager;
+use crate::certificate::PendingExchange;
+use crate::dht::PendingQuery;
 use crate::types::P2pRequest;
-use libp2p::{Multiaddr, PeerId, Swarm, futures::StreamExt, identity::Keypair};
-use libp2p_gossipsub::{IdentTopic, TopicHash};
-use std::collections::{HashMap, HashSet};
-use types::{ReceivedConnection, ReceivedMessage};
+use anyhow::Context;
+use config::{AddressSettings, AuthorizationSettings, P2pNodeConfig, TransportPreference};
+use error::P2pError;
+use handle::P2pHandle;
+use libp2p::{
+    Multiaddr, PeerId, Swarm, Transport,
+    core::transport::ListenerId,
+    futures::{StreamExt, future::BoxFuture, stream::FuturesUnordered},
+    identify,
+    identity::Keypair,
+    kad,
+    request_response::{OutboundRequestId, ResponseChannel},
+};
+use libp2p_gossipsub::{IdentTopic, MessageAcceptance, MessageId, TopicHash};
+use std::{
+    collections::{HashMap, HashSet},
+    num::NonZeroUsize,
+    ops::ControlFlow,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+use subscription::{SubscriptionSettings, TopicCounters, TopicReceiver, TopicSender};
+use tokio::task::JoinHandle;
+use topic::TopicDecoder;
+use types::{
+    ConnectionAuthorizationRequest, InboundRequest, MessageValidationRequest, P2pEvent, PeerEvent,
+    PeerInfo, ReceivedMessage, Responder,
+};
 
+#[cfg(feature = "auth-rs")]
+pub mod authorizer;
+mod behavior;
+pub mod bootstrap;
 pub mod builder;
+mod certificate;
+pub mod codec;
+pub mod config;
+pub mod dht;
+mod direct;
+pub mod error;
+mod events;
+mod framing;
+pub mod handle;
+mod store;
+pub mod subscription;
+pub mod topic;
 pub mod traits;
 pub mod types;
-mod behavior;
-mod events;
 
 const DEFAULT_LISTENING_PORT: u16 = 1123;
 const CHANNEL_SIZE: usize = 1000;
+const MESSAGE_VALIDATION_TIMEOUT: Duration = Duration::from_secs(2);
+const PEER_SCORE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const PERSISTENCE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A peer waiting for the authorization verdict: peer id, identify infos, certified and verdict if received in time
+type PendingAuthorization = BoxFuture<'static, (PeerId, identify::Info, bool, Option<bool>)>;
+
+/// A direct request waiting for the handler response, None if the handler did not answer in time
+type PendingResponse = BoxFuture<'static, (ResponseChannel<Vec<u8>>, Option<Vec<u8>>)>;
+
+/// A message waiting for the validator verdict: message id, propagation source, message and verdict
+type PendingValidation =
+    BoxFuture<'static, (MessageId, PeerId, ReceivedMessage, MessageAcceptance)>;
 
 /// A P2P network node, use kademlia DHT and gossipsub protocol
 pub struct P2pNode {
@@ -21,38 +76,101 @@ pub struct P2pNode {
     /// The node peer id
     pub peer_id: PeerId,
     pub swarm: Swarm<P2pBehavior>,
-    /// Connected peers
+    /// Connected peers accepted by the connection authorization
     pub peers: HashSet<PeerId>,
+    /// Connected peers, with their addresses, connections and certificate state
+    peer_table: HashMap<PeerId, PeerInfo>,
+    /// Connected peers that were authorized with a certificate
+    pub certified_peers: HashSet<PeerId>,
     /// Subscribed gossipsub topics with name and hash
     pub gossipsub_topics: HashMap<TopicHash, String>,
-    /// The address the node is listening on
-    pub listening_address: Multiaddr,
+    /// The addresses the node listens on when started
+    pub listening_addresses: HashSet<Multiaddr>,
+    /// The active listeners, by requested address
+    listeners: HashMap<Multiaddr, ListenerId>,
+    /// Which transports the node uses, and which one is dialed first
+    pub transport_preference: TransportPreference,
     /// An optional certificate, can be used to identify the node
     pub identify_certificate: Option<String>,
+    /// The decoded certificate, sent to the peers through the certificate exchange
+    certificate: Option<Vec<u8>>,
+    /// The certificate exchanges waiting for the peer response
+    pending_exchanges: HashMap<PeerId, PendingExchange>,
     /// The bootstrap nodes addresses, to enter the network and discover other peers
     pub bootstrap_nodes: HashSet<Multiaddr>,
+    /// Redials the bootstrap nodes while the node has too few authorized peers
+    bootstrap: BootstrapManager,
+    /// The directory where the kademlia records and routing table are saved, if persistence is enabled
+    pub persistence_dir: Option<PathBuf>,
+    /// The number of peers a record is replicated to, the highest quorum a query can reach
+    replication_factor: NonZeroUsize,
+    /// The running save of the kademlia store
+    saving: Option<JoinHandle<std::io::Result<Option<u64>>>>,
+    /// An optional channel to answer the direct requests of other peers
+    pub request_handler_tx: Option<tokio::sync::mpsc::Sender<InboundRequest>>,
+    /// How long the node waits for the request handler
+    handler_timeout: Duration,
+    /// The direct requests sent through the node API, waiting for the peer response
+    pending_requests: HashMap<OutboundRequestId, Responder<Result<Vec<u8>, P2pError>>>,
+    /// The direct requests received from peers, waiting for the handler response
+    pending_responses: FuturesUnordered<PendingResponse>,
+    /// The DHT queries started through the node API
+    pending_queries: HashMap<kad::QueryId, PendingQuery>,
     /// The channel to send received messages, with payload, source, topic...
     pub received_messages_tx: tokio::sync::broadcast::Sender<ReceivedMessage>,
+    /// The channel to send node events, such as remote peers joining or leaving a topic
+    pub events_tx: tokio::sync::broadcast::Sender<P2pEvent>,
+    /// The channel to send peer lifecycle events
+    pub peer_events_tx: tokio::sync::broadcast::Sender<PeerEvent>,
     /// The channel to receive messages to send to other peers
     pub send_messages_rx: tokio::sync::mpsc::Receiver<P2pRequest>,
     /// Used to send the informations about a peers that sent a connection request and a oneshot channel to wait for the authorization (true / false)
-    pub connection_authorization_tx:
-        tokio::sync::mpsc::Sender<(ReceivedConnection, tokio::sync::oneshot::Sender<bool>)>,
+    pub connection_authorization_tx: tokio::sync::mpsc::Sender<ConnectionAuthorizationRequest>,
+    /// Which peer addresses are added to kademlia and how external addresses are confirmed
+    pub address_settings: AddressSettings,
+    /// The addresses of the peers discovered with mDNS, added to kademlia once the peers are authorized
+    mdns_addresses: HashMap<PeerId, HashSet<Multiaddr>>,
+    /// The authorized peers that observed each address of the node, confirmed as external once a quorum is reached
+    observed_addresses: HashMap<Multiaddr, HashSet<PeerId>>,
+    /// The authorization timeout and the verdict applied when no verdict is received in time
+    pub authorization_settings: AuthorizationSettings,
+    /// The peers waiting for the authorization verdict
+    pending_authorizations: FuturesUnordered<PendingAuthorization>,
+    /// The peers with a pending authorization, so a peer is not authorized twice at the same time
+    authorizing_peers: HashSet<PeerId>,
+    /// An optional channel to send received messages for validation before they are propagated and delivered
+    pub message_validator_tx: Option<tokio::sync::mpsc::Sender<MessageValidationRequest>>,
+    /// The received messages waiting for the validator verdict
+    pending_validations: FuturesUnordered<PendingValidation>,
+    /// The dedicated receivers of each topic
+    topic_senders: HashMap<TopicHash, Vec<TopicSender>>,
+    /// The delivery metrics of each topic, shared with the topic receivers
+    topic_counters: HashMap<TopicHash, Arc<TopicCounters>>,
+    /// The decoders of the typed topics, used to reject the messages that cannot be decoded
+    topic_decoders: HashMap<TopicHash, TopicDecoder>,
+    /// The peer score below which peers are graylisted, if peer scoring is enabled
+    graylist_threshold: Option<f64>,
+    /// The peers currently below the graylist threshold
+    graylisted_peers: HashSet<PeerId>,
 }
 
 impl P2pNode {
     pub fn new(
         keypair: Keypair,
-        listening_address: Multiaddr,
-        bootstrap_nodes: HashSet<Multiaddr>,
-        identify_certificate: Option<String>,
-        gossipsub_topics: HashSet<String>,
+        config: P2pNodeConfig,
     ) -> anyhow::Result<(
         Self,
-        tokio::sync::broadcast::Receiver<ReceivedMessage>,
-        tokio::sync::mpsc::Sender<P2pRequest>,
-        tokio::sync::mpsc::Receiver<(ReceivedConnection, tokio::sync::oneshot::Sender<bool>)>,
+        P2pHandle,
+        tokio::sync::mpsc::Receiver<ConnectionAuthorizationRequest>,
     )> {
+        let bootstrap = BootstrapManager::new(config.bootstrap_settings)?;
+        let replication_factor = config.kademlia_settings.replication_factor;
+        let certificate = config
+            .identify_certificate
+            .as_deref()
+            .map(hex::decode)
+            .transpose()
+            .context("Invalid identify certificate, expected hex")?;
         let mut swarm = libp2p::SwarmBuilder::with_existing_identity(keypair.clone())
             .with_tokio()
             .with_tcp(
@@ -60,58 +178,188 @@ impl P2pNode {
                 (libp2p::tls::Config::new, libp2p::noise::Config::new),
                 libp2p::yamux::Config::default,
             )?
+            .with_quic()
+            .with_other_transport(|keypair| {
+                Ok::<_, Box<dyn std::error::Error + Send + Sync>>(
+                    libp2p::websocket::WsConfig::new(libp2p::tcp::tokio::Transport::new(
+                        Default::default(),
+                    ))
+                    .upgrade(libp2p::core::upgrade::Version::V1Lazy)
+                    .authenticate(libp2p::noise::Config::new(keypair)?)
+                    .multiplex(libp2p::yamux::Config::default()),
+                )
+            })?
+            .with_dns()?
             .with_behaviour(|identity| {
-                P2pBehavior::new(identity.clone(), identify_certificate.clone()).unwrap() //safe: TODO: remove unwrap
+                P2pBehavior::new(identity.clone(), &config).map_err(Into::into)
             })?
             .build();
 
-        let (received_messages_tx, received_messages_rx) =
-            tokio::sync::broadcast::channel(CHANNEL_SIZE);
+        let kademlia = &mut swarm.behaviour_mut().kademlia;
+        let known_peers = kademlia.store_mut().load_peers();
+        if !known_peers.is_empty() {
+            tracing::info!("Restoring {} saved kademlia peers", known_peers.len());
+        }
+        for (peer_id, addresses) in known_peers {
+            for address in addresses {
+                kademlia.add_address(&peer_id, address);
+            }
+        }
+
+        let (received_messages_tx, _) = tokio::sync::broadcast::channel(CHANNEL_SIZE);
+        let (events_tx, _) = tokio::sync::broadcast::channel(CHANNEL_SIZE);
+        let (peer_events_tx, _) = tokio::sync::broadcast::channel(CHANNEL_SIZE);
         let (send_messages_tx, send_messages_rx) = tokio::sync::mpsc::channel(CHANNEL_SIZE);
         let (connection_authorization_tx, connection_authorization_rx) =
             tokio::sync::mpsc::channel(CHANNEL_SIZE);
 
         let mut sub_topics = HashMap::new();
-        for topic in gossipsub_topics {
+        for topic in config.gossipsub_topics {
             let topic_id = libp2p_gossipsub::IdentTopic::new(&topic);
             swarm.behaviour_mut().gossipsub.subscribe(&topic_id)?;
             sub_topics.insert(topic_id.hash(), topic);
         }
 
+        let handle = P2pHandle::new(
+            send_messages_tx,
+            received_messages_tx.clone(),
+            events_tx.clone(),
+            peer_events_tx.clone(),
+        );
+
         Ok((
             Self {
                 send_messages_rx,
                 keypair,
                 peer_id: *swarm.local_peer_id(),
                 swarm,
-                listening_address,
-                identify_certificate,
-                bootstrap_nodes,
+                listening_addresses: config.listening_addresses,
+                listeners: HashMap::new(),
+                transport_preference: config.transport_preference,
+                identify_certificate: config.identify_certificate,
+                certificate,
+                pending_exchanges: HashMap::new(),
+                bootstrap_nodes: config.bootstrap_nodes,
+                bootstrap,
+                pending_queries: HashMap::new(),
+                request_handler_tx: config.request_handler_tx,
+                handler_timeout: config.direct_messaging_settings.handler_timeout,
+                pending_requests: HashMap::new(),
+                pending_responses: FuturesUnordered::new(),
+                persistence_dir: config.persistence_dir,
+                replication_factor,
+                saving: None,
                 received_messages_tx,
+                events_tx,
+                peer_events_tx,
                 peers: HashSet::new(),
+                peer_table: HashMap::new(),
+                certified_peers: HashSet::new(),
                 gossipsub_topics: sub_topics,
                 connection_authorization_tx,
+                authorization_settings: config.authorization_settings,
+                address_settings: config.address_settings,
+                observed_addresses: HashMap::new(),
+                mdns_addresses: HashMap::new(),
+                pending_authorizations: FuturesUnordered::new(),
+                authorizing_peers: HashSet::new(),
+                message_validator_tx: config.message_validator_tx,
+                pending_validations: FuturesUnordered::new(),
+                graylist_threshold: config
+                    .peer_score
+                    .map(|peer_score| peer_score.thresholds.graylist_threshold),
+                graylisted_peers: HashSet::new(),
+                topic_decoders: HashMap::new(),
+                topic_senders: HashMap::new(),
+                topic_counters: HashMap::new(),
             },
-            received_messages_rx,
-            send_messages_tx,
+            handle,
             connection_authorization_rx,
         ))
     }
     /// Subscribe to a gossipsub topic of a given name, returning the hash of the topic
-    pub fn subscribe_topic(&mut self, topic: &str) -> anyhow::Result<TopicHash> {
-        let topic = libp2p_gossipsub::IdentTopic::new(topic);
-        self.swarm.behaviour_mut().gossipsub.subscribe(&topic)?;
-        Ok(topic.hash())
+    pub fn subscribe_topic(&mut self, topic: &str) -> Result<TopicHash, P2pError> {
+        let topic_id = libp2p_gossipsub::IdentTopic::new(topic);
+        self.swarm.behaviour_mut().gossipsub.subscribe(&topic_id)?;
+        self.gossipsub_topics
+            .insert(topic_id.hash(), topic.to_string());
+        Ok(topic_id.hash())
+    }
+    /// Create a dedicated receiver of the messages of a subscribed topic
+    pub fn topic_receiver(&mut self, topic: &str, settings: SubscriptionSettings) -> TopicReceiver {
+        let topic_hash = libp2p_gossipsub::IdentTopic::new(topic).hash();
+        let counters = self.topic_counters.entry(topic_hash.clone()).or_default();
+        let (sender, receiver) =
+            TopicSender::channel(topic.to_string(), settings, counters.clone());
+        self.topic_senders
+            .entry(topic_hash)
+            .or_default()
+            .push(sender);
+        receiver
+    }
+    /// Unsubscribe from a gossipsub topic of a given name, returns false if the node was not subscribed
+    pub fn unsubscribe_topic(&mut self, topic: &str) -> Result<bool, P2pError> {
+        let topic_id = libp2p_gossipsub::IdentTopic::new(topic);
+        self.gossipsub_topics.remove(&topic_id.hash());
+        self.topic_decoders.remove(&topic_id.hash());
+        self.topic_senders.remove(&topic_id.hash());
+        Ok(self
+            .swarm
+            .behaviour_mut()
+            .gossipsub
+            .unsubscribe(&topic_id)?)
     }
     pub async fn run(mut self) -> anyhow::Result<()> {
         tracing::info!("Starting P2P node");
-        self.swarm.listen_on(self.listening_address.clone())?;
+        let mut listening = false;
+        for address in self.listening_addresses.clone() {
+            match self.add_listener(address.clone()) {
+                Ok(()) => listening = true,
+                Err(e) => tracing::error!("Could not listen on address {address}: {e}"),
+            }
+        }
+        if !listening {
+            anyhow::bail!("Could not listen on any address");
+        }
         self.try_dial_bootstrap_nodes();
+        if self.persistence_dir.is_some() && self.swarm.behaviour_mut().kademlia.bootstrap().is_ok()
+        {
+            tracing::info!("Rejoining the network through the saved kademlia peers");
+        }
+        let mut peer_score_interval = tokio::time::interval(PEER_SCORE_CHECK_INTERVAL);
+        let mut bootstrap_interval = tokio::time::interval(self.bootstrap.check_interval());
+        let mut persistence_interval = tokio::time::interval(PERSISTENCE_INTERVAL);
         loop {
             tokio::select! {
                     Some(req) = self.send_messages_rx.recv() => {
-                        //TODO: handle error ??
-                        let _ = self.handle_p2p_request(req);
+                        if let ControlFlow::Break(responder) = self.handle_p2p_request(req) {
+                            tracing::info!("Shutting down P2P node");
+                            self.persist_on_shutdown().await;
+                            let _ = responder.send(());
+                            return Ok(());
+                        }
+                    }
+                    Some((message_id, propagation_source, message, acceptance)) = self.pending_validations.next(), if !self.pending_validations.is_empty() => {
+                        self.handle_validation_result(message_id, propagation_source, message, acceptance).await;
+                    }
+                    Some((peer_id, info, certified, verdict)) = self.pending_authorizations.next(), if !self.pending_authorizations.is_empty() => {
+                        self.handle_authorization_result(peer_id, info, certified, verdict);
+                    }
+                    Some((channel, response)) = self.pending_responses.next(), if !self.pending_responses.is_empty() => {
+                        self.handle_request_response(channel, response);
+                    }
+                    _ = peer_score_interval.tick(), if self.graylist_threshold.is_some() => {
+                        self.check_peer_scores();
+                    }
+                    _ = persistence_interval.tick(), if self.persistence_dir.is_some() => {
+                        self.persist().await;
+                    }
+                    _ = bootstrap_interval.tick(), if !self.bootstrap_nodes.is_empty() => {
+                        let authorized_peers = self.peers.len();
+                        if self.bootstrap.should_redial(authorized_peers) {
+                            tracing::info!("Only {authorized_peers} authorized peers, redialing bootstrap nodes");
+                            self.try_dial_bootstrap_nodes();
+                        }
                     }
                     event = self.swarm.select_next_some() => {
                     self.handle_swarm_event(event).await?;
@@ -119,26 +367,293 @@ impl P2pNode {
             }
         }
     }
-    fn handle_p2p_request(&mut self, req: P2pRequest) -> anyhow::Result<()> {
+    /// Answer a request of the handle, breaks with the responder of the shutdown request
+    fn handle_p2p_request(&mut self, req: P2pRequest) -> ControlFlow<Responder<()>> {
         match req {
-            P2pRequest::Broadcast(topic, data) => {
+            P2pRequest::Broadcast(topic, data, responder) => {
                 let topic_id = IdentTopic::new(&topic);
-                //TODO: warn log if topic not in subscriber topics
-                match self.swarm.behaviour_mut().gossipsub.publish(topic_id, data) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        tracing::error!("Failed to publish a message on topic {} : {}", topic, e)
+                if !self.gossipsub_topics.contains_key(&topic_id.hash()) {
+                    tracing::warn!("Publishing a message on unsubscribed topic {}", topic);
+                }
+                let result = self.swarm.behaviour_mut().gossipsub.publish(topic_id, data);
+                if let Err(e) = &result {
+                    tracing::error!("Failed to publish a message on topic {} : {}", topic, e)
+                }
+                if let Some(responder) = responder {
+                    let _ = responder.send(result.map_err(Into::into));
+                }
+            }
+            P2pRequest::Subscribe(topic, settings, decoder, responder) => {
+                let result = self.subscribe_topic(&topic).map(|topic_hash| {
+                    if let Some(decoder) = decoder {
+                        self.topic_decoders.insert(topic_hash, decoder);
+                    }
+                    self.topic_receiver(&topic, settings)
+                });
+                match &result {
+                    Ok(_) => tracing::info!("Subscribed to topic {}", topic),
+                    Err(e) => tracing::error!("Failed to subscribe to topic {} : {}", topic, e),
+                }
+                let _ = responder.send(result);
+            }
+            P2pRequest::Unsubscribe(topic, responder) => {
+                let result = self.unsubscribe_topic(&topic);
+                if let Err(e) = &result {
+                    tracing::error!("Failed to unsubscribe from topic {} : {}", topic, e)
+                }
+                let _ = responder.send(result);
+            }
+            P2pRequest::ConnectedPeers(responder) => {
+                let _ = responder.send(self.swarm.connected_peers().copied().collect());
+            }
+            P2pRequest::PutRecord(key, value, quorum, responder) => {
+                // kademlia stores the record on at most replication factor peers
+                let quorum = quorum.min(self.replication_factor);
+                let query_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .put_record(kad::Record::new(key, value), kad::Quorum::N(quorum));
+                self.start_query(query_id, PendingQuery::PutRecord { quorum, responder });
+            }
+            P2pRequest::GetRecord(key, quorum, responder) => {
+                let quorum = quorum.min(self.replication_factor);
+                let query_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .get_record(kad::RecordKey::new(&key));
+                self.start_query(
+                    Ok(query_id),
+                    PendingQuery::GetRecord {
+                        quorum,
+                        records: Vec::new(),
+                        responder,
+                    },
+                );
+            }
+            P2pRequest::StartProviding(key, responder) => {
+                let query_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .start_providing(kad::RecordKey::new(&key));
+                self.start_query(query_id, PendingQuery::StartProviding { responder });
+            }
+            P2pRequest::GetProviders(key, responder) => {
+                let query_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .get_providers(kad::RecordKey::new(&key));
+                self.start_query(
+                    Ok(query_id),
+                    PendingQuery::GetProviders {
+                        providers: HashSet::new(),
+                        responder,
+                    },
+                );
+            }
+            P2pRequest::FindClosestPeers(peer_id, responder) => {
+                let query_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .get_closest_peers(peer_id);
+                self.start_query(Ok(query_id), PendingQuery::FindClosestPeers { responder });
+            }
+            P2pRequest::SendRequest(peer_id, data, responder) => {
+                let request_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .direct_messages
+                    .send_request(&peer_id, data);
+                self.pending_requests.insert(request_id, responder);
+            }
+            P2pRequest::BootstrapStatus(responder) => {
+                let _ = responder.send(self.bootstrap.status(self.peers.len()));
+            }
+            P2pRequest::PeerTable(responder) => {
+                let _ = responder.send(self.peer_table.clone());
+            }
+            P2pRequest::Dial(address, responder) => {
+                let result = match self.transport_preference.priority(&address) {
+                    Some(_) => self.swarm.dial(address).map_err(Into::into),
+                    None => Err(P2pError::TransportDisabled(address)),
+                };
+                let _ = responder.send(result);
+            }
+            P2pRequest::TopicMetrics(responder) => {
+                let metrics = self
+                    .topic_counters
+                    .iter()
+                    .map(|(topic_hash, counters)| {
+                        (self.topic_name(topic_hash), counters.snapshot())
+                    })
+                    .collect();
+                let _ = responder.send(metrics);
+            }
+            P2pRequest::PeerScores(responder) => {
+                let _ = responder.send(self.peer_scores());
+            }
+            P2pRequest::AddListener(address, responder) => {
+                let _ = responder.send(self.add_listener(address));
+            }
+            P2pRequest::RemoveListener(address, responder) => {
+                let _ = responder.send(self.remove_listener(&address));
+            }
+            P2pRequest::ListenAddrs(responder) => {
+                let _ = responder.send(self.swarm.listeners().cloned().collect());
+            }
+            P2pRequest::BlockPeer(peer_id, responder) => {
+                tracing::info!("Blocking peer {peer_id}");
+                self.swarm.behaviour_mut().blocked_peers.block_peer(peer_id);
+                let _ = responder.send(());
+            }
+            P2pRequest::UnblockPeer(peer_id, responder) => {
+                tracing::info!("Unblocking peer {peer_id}");
+                let behaviour = self.swarm.behaviour_mut();
+                behaviour.blocked_peers.unblock_peer(peer_id);
+                behaviour.gossipsub.remove_blacklisted_peer(&peer_id);
+                let _ = responder.send(());
+            }
+            P2pRequest::AllowPeer(peer_id, responder) => {
+                let result = match self.swarm.behaviour_mut().allowed_peers.as_mut() {
+                    Some(allowed_peers) => {
+                        allowed_peers.allow_peer(peer_id);
+                        Ok(())
+                    }
+                    None => Err(P2pError::AllowListDisabled),
+                };
+                let _ = responder.send(result);
+            }
+            P2pRequest::DisallowPeer(peer_id, responder) => {
+                let result = match self.swarm.behaviour_mut().allowed_peers.as_mut() {
+                    Some(allowed_peers) => {
+                        allowed_peers.disallow_peer(peer_id);
+                        Ok(())
                     }
+                    None => Err(P2pError::AllowListDisabled),
+                };
+                let _ = responder.send(result);
+            }
+            P2pRequest::SetConnectionLimits(limits, responder) => {
+                *self.swarm.behaviour_mut().connection_limits.limits_mut() = limits.to_limits();
+                let _ = responder.send(());
+            }
+            P2pRequest::Shutdown(responder) => return ControlFlow::Break(responder),
+        }
+        ControlFlow::Continue(())
+    }
+    /// The gossipsub score of the known peers, empty if peer scoring is disabled
+    fn peer_scores(&self) -> HashMap<PeerId, f64> {
+        let gossipsub = &self.swarm.behaviour().gossipsub;
+        gossipsub
+            .all_peers()
+            .filter_map(|(peer_id, _)| Some((*peer_id, gossipsub.peer_score(peer_id)?)))
+            .collect()
+    }
+    /// Emit an event for each peer that dropped below the graylist threshold since the last check
+    fn check_peer_scores(&mut self) {
+        let Some(graylist_threshold) = self.graylist_threshold else {
+            return;
+        };
+        for (peer_id, score) in self.peer_scores() {
+            if score < graylist_threshold {
+                if self.graylisted_peers.insert(peer_id) {
+                    tracing::warn!("Peer {peer_id} graylisted with a score of {score}");
+                    self.emit_event(P2pEvent::PeerGraylisted { peer_id, score });
                 }
+            } else {
+                self.graylisted_peers.remove(&peer_id);
             }
         }
+    }
+    /// Listen on an address, the bound addresses are reported once the listener is ready
+    pub fn add_listener(&mut self, address: Multiaddr) -> Result<(), P2pError> {
+        if self.transport_preference.priority(&address).is_none() {
+            return Err(P2pError::TransportDisabled(address));
+        }
+        if self.listeners.contains_key(&address) {
+            return Ok(());
+        }
+        let listener_id = self.swarm.listen_on(address.clone())?;
+        self.listeners.insert(address, listener_id);
         Ok(())
     }
+    /// Stop listening on an address, returns false if the node was not listening on it
+    pub fn remove_listener(&mut self, address: &Multiaddr) -> bool {
+        match self.listeners.remove(address) {
+            Some(listener_id) => self.swarm.remove_listener(listener_id),
+            None => false,
+        }
+    }
+    /// Save the kademlia records and routing table in a blocking task, if persistence is enabled and no save is running
+    async fn persist(&mut self) {
+        match self.saving.take() {
+            Some(task) if !task.is_finished() => {
+                tracing::debug!("The previous save of the kademlia store is still running");
+                self.saving = Some(task);
+                return;
+            }
+            Some(task) => self.saved(task.await),
+            None => {}
+        }
+        let kademlia = &mut self.swarm.behaviour_mut().kademlia;
+        let peers: Vec<_> = kademlia
+            .kbuckets()
+            .flat_map(|bucket| {
+                bucket
+                    .iter()
+                    .map(|entry| {
+                        let addresses = entry.node.value.iter().cloned().collect();
+                        (*entry.node.key.preimage(), addresses)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let snapshot = match kademlia.store_mut().snapshot(peers.into_iter()) {
+            Ok(Some(snapshot)) => snapshot,
+            Ok(None) => return,
+            Err(e) => return tracing::error!("Failed to encode the kademlia store: {e}"),
+        };
+        self.saving = Some(tokio::task::spawn_blocking(move || snapshot.write()));
+    }
+    /// Mark the records as saved once a save succeeded, they are saved again with the next save otherwise
+    fn saved(&mut self, result: Result<std::io::Result<Option<u64>>, tokio::task::JoinError>) {
+        match result {
+            Ok(Ok(Some(version))) => self
+                .swarm
+                .behaviour_mut()
+                .kademlia
+                .store_mut()
+                .saved(version),
+            Ok(Ok(None)) => {}
+            Ok(Err(e)) => tracing::error!("Failed to save the kademlia store: {e}"),
+            Err(e) => tracing::error!("The save of the kademlia store failed: {e}"),
+        }
+    }
+    /// Save the kademlia store a last time before the node stops, waiting for the running save if any
+    async fn persist_on_shutdown(&mut self) {
+        if let Some(task) = self.saving.take() {
+            self.saved(task.await);
+        }
+        self.persist().await;
+        if let Some(task) = self.saving.take() {
+            self.saved(task.await);
+        }
+    }
     fn try_dial_bootstrap_nodes(&mut self) {
         if self.bootstrap_nodes.is_empty() {
             tracing::warn!("No bootstrap nodes provided");
         } else {
-            for addr in &self.bootstrap_nodes {
+            let mut bootstrap_nodes: Vec<_> = self
+                .bootstrap_nodes
+                .iter()
+                .filter_map(|addr| Some((self.transport_preference.priority(addr)?, addr.clone())))
+                .collect();
+            bootstrap_nodes.sort_by_key(|(priority, _)| *priority);
+            for (_, addr) in bootstrap_nodes {
                 match self.swarm.dial(addr.clone()) {
                     Err(e) => {
                         tracing::error!(
@@ -152,6 +667,7 @@ impl P2pNode {
                     }
                 }
             }
+            self.bootstrap.attempted();
         }
     }
 }
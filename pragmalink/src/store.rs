@@ -0,0 +1,410 @@
+use crate::framing::{read_field, write_field};
+use libp2p::{
+    Multiaddr, PeerId,
+    kad::{
+        ProviderRecord, Record, RecordKey,
+        store::{self, MemoryStore, RecordStore},
+    },
+};
+use std::{
+    borrow::Cow,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const RECORDS_FILE: &str = "records";
+const PROVIDED_FILE: &str = "provided";
+const PEERS_FILE: &str = "peers";
+
+/// The kademlia record store: in memory, and saved to a directory if persistence is enabled.
+/// The records and the keys provided by the node are saved, the providers of other peers are not
+pub struct PersistentStore {
+    inner: MemoryStore,
+    directory: Option<PathBuf>,
+    /// Incremented on each change of the records
+    version: u64,
+    /// The version of the records last written to the directory
+    saved_version: u64,
+}
+
+impl PersistentStore {
+    /// Open the store, loading the records saved in the directory if any.
+    /// A file that cannot be read or decoded is skipped, the node starts without its entries
+    pub fn open(local_peer_id: PeerId, directory: Option<PathBuf>) -> io::Result<Self> {
+        let mut inner = MemoryStore::new(local_peer_id);
+        if let Some(directory) = &directory {
+            fs::create_dir_all(directory)?;
+            for record in load_entries(&directory.join(RECORDS_FILE), decode_record) {
+                if let Err(e) = inner.put(record) {
+                    tracing::warn!("Could not restore a kademlia record: {e}");
+                }
+            }
+            for record in load_entries(&directory.join(PROVIDED_FILE), decode_provider) {
+                if let Err(e) = inner.add_provider(record) {
+                    tracing::warn!("Could not restore a kademlia provider record: {e}");
+                }
+            }
+        }
+        Ok(Self {
+            inner,
+            directory,
+            version: 0,
+            saved_version: 0,
+        })
+    }
+    /// Encode the records if they changed and the peers of the routing table with their addresses,
+    /// None if persistence is disabled. The snapshot is written outside of the swarm event loop,
+    /// the records are only marked as saved with [`Self::saved`] once the write succeeded
+    pub(crate) fn snapshot(
+        &mut self,
+        peers: impl Iterator<Item = (PeerId, Vec<Multiaddr>)>,
+    ) -> io::Result<Option<StoreSnapshot>> {
+        let Some(directory) = &self.directory else {
+            return Ok(None);
+        };
+        let mut files = Vec::new();
+        let mut version = None;
+        if self.version != self.saved_version {
+            files.push((
+                RECORDS_FILE,
+                encode_entries(self.inner.records(), |record, bytes| {
+                    encode_record(&record, bytes)
+                })?,
+            ));
+            files.push((
+                PROVIDED_FILE,
+                encode_entries(self.inner.provided(), |record, bytes| {
+                    encode_provider(&record, bytes)
+                })?,
+            ));
+            version = Some(self.version);
+        }
+        files.push((
+            PEERS_FILE,
+            encode_entries(peers, |(peer_id, addresses), bytes| {
+                write_field(bytes, &peer_id.to_bytes())?;
+                encode_addresses(&addresses, bytes)
+            })?,
+        ));
+        Ok(Some(StoreSnapshot {
+            directory: directory.clone(),
+            files,
+            version,
+        }))
+    }
+    /// Mark the records of a written snapshot as saved, the changes made since the snapshot are saved with the next one
+    pub(crate) fn saved(&mut self, version: u64) {
+        self.saved_version = self.saved_version.max(version);
+    }
+    /// The peers of the routing table saved in the directory, empty if persistence is disabled or the file is invalid
+    pub(crate) fn load_peers(&self) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        let Some(directory) = &self.directory else {
+            return Vec::new();
+        };
+        load_entries(&directory.join(PEERS_FILE), |bytes| {
+            Ok(Some((
+                decode_peer_id(&read_field(bytes)?)?,
+                decode_addresses(bytes)?,
+            )))
+        })
+    }
+}
+
+/// The encoded files of the store, waiting to be written to the directory
+pub(crate) struct StoreSnapshot {
+    directory: PathBuf,
+    files: Vec<(&'static str, Vec<u8>)>,
+    /// The version of the records in the snapshot, None if the records did not change
+    version: Option<u64>,
+}
+
+impl StoreSnapshot {
+    /// Write each file to a temporary file, then replace the file, returns the version of the written records.
+    /// Blocking, run it outside of the async runtime
+    pub(crate) fn write(self) -> io::Result<Option<u64>> {
+        for (name, bytes) in self.files {
+            let path = self.directory.join(name);
+            let tmp_path = path.with_extension("tmp");
+            fs::write(&tmp_path, bytes)?;
+            fs::rename(tmp_path, path)?;
+        }
+        Ok(self.version)
+    }
+}
+
+impl RecordStore for PersistentStore {
+    type RecordsIter<'a> = <MemoryStore as RecordStore>::RecordsIter<'a>;
+    type ProvidedIter<'a> = <MemoryStore as RecordStore>::ProvidedIter<'a>;
+
+    fn get(&self, k: &RecordKey) -> Option<Cow<'_, Record>> {
+        self.inner.get(k)
+    }
+    fn put(&mut self, r: Record) -> store::Result<()> {
+        self.inner.put(r)?;
+        self.version += 1;
+        Ok(())
+    }
+    fn remove(&mut self, k: &RecordKey) {
+        self.inner.remove(k);
+        self.version += 1;
+    }
+    fn records(&self) -> Self::RecordsIter<'_> {
+        self.inner.records()
+    }
+    fn add_provider(&mut self, record: ProviderRecord) -> store::Result<()> {
+        self.inner.add_provider(record)?;
+        self.version += 1;
+        Ok(())
+    }
+    fn providers(&self, key: &RecordKey) -> Vec<ProviderRecord> {
+        self.inner.providers(key)
+    }
+    fn provided(&self) -> Self::ProvidedIter<'_> {
+        self.inner.provided()
+    }
+    fn remove_provider(&mut self, k: &RecordKey, p: &PeerId) {
+        self.inner.remove_provider(k, p);
+        self.version += 1;
+    }
+}
+
+/// Encode the entries, each entry is a length prefixed field
+fn encode_entries<T>(
+    entries: impl Iterator<Item = T>,
+    encode: impl Fn(T, &mut Vec<u8>) -> io::Result<()>,
+) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    for entry in entries {
+        let mut entry_bytes = Vec::new();
+        encode(entry, &mut entry_bytes)?;
+        write_field(&mut bytes, &entry_bytes)?;
+    }
+    Ok(bytes)
+}
+
+/// Decode the entries of a file. Expired entries are decoded as None and skipped
+fn decode_entries<T>(
+    mut bytes: &[u8],
+    decode: impl Fn(&mut &[u8]) -> io::Result<Option<T>>,
+) -> io::Result<Vec<T>> {
+    let mut entries = Vec::new();
+    while !bytes.is_empty() {
+        let entry = read_field(&mut bytes)?;
+        entries.extend(decode(&mut entry.as_slice())?);
+    }
+    Ok(entries)
+}
+
+/// Read the entries of a file, an absent file has no entries.
+/// A file that cannot be read or decoded is skipped with a warning, so a corrupted file does not prevent the node from starting
+fn load_entries<T>(path: &Path, decode: impl Fn(&mut &[u8]) -> io::Result<Option<T>>) -> Vec<T> {
+    let entries = match fs::read(path) {
+        Ok(bytes) => decode_entries(&bytes, decode),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Vec::new(),
+        Err(e) => Err(e),
+    };
+    entries.unwrap_or_else(|e| {
+        tracing::warn!(
+            "Could not load {}, starting without it: {e}",
+            path.display()
+        );
+        Vec::new()
+    })
+}
+
+fn encode_record(record: &Record, bytes: &mut Vec<u8>) -> io::Result<()> {
+    write_field(bytes, record.key.as_ref())?;
+    write_field(bytes, &record.value)?;
+    write_field(
+        bytes,
+        &record
+            .publisher
+            .map(|peer_id| peer_id.to_bytes())
+            .unwrap_or_default(),
+    )?;
+    encode_expires(record.expires, bytes)
+}
+
+fn decode_record(bytes: &mut &[u8]) -> io::Result<Option<Record>> {
+    let key = RecordKey::from(read_field(bytes)?);
+    let value = read_field(bytes)?;
+    let publisher = read_field(bytes)?;
+    let publisher = (!publisher.is_empty())
+        .then(|| decode_peer_id(&publisher))
+        .transpose()?;
+    let Some(expires) = decode_expires(bytes)? else {
+        return Ok(None);
+    };
+    Ok(Some(Record {
+        key,
+        value,
+        publisher,
+        expires,
+    }))
+}
+
+fn encode_provider(record: &ProviderRecord, bytes: &mut Vec<u8>) -> io::Result<()> {
+    write_field(bytes, record.key.as_ref())?;
+    write_field(bytes, &record.provider.to_bytes())?;
+    encode_addresses(&record.addresses, bytes)?;
+    encode_expires(record.expires, bytes)
+}
+
+fn decode_provider(bytes: &mut &[u8]) -> io::Result<Option<ProviderRecord>> {
+    let key = RecordKey::from(read_field(bytes)?);
+    let provider = decode_peer_id(&read_field(bytes)?)?;
+    let addresses = decode_addresses(bytes)?;
+    let Some(expires) = decode_expires(bytes)? else {
+        return Ok(None);
+    };
+    Ok(Some(ProviderRecord {
+        key,
+        provider,
+        expires,
+        addresses,
+    }))
+}
+
+fn encode_addresses(addresses: &[Multiaddr], bytes: &mut Vec<u8>) -> io::Result<()> {
+    let mut addresses_bytes = Vec::new();
+    for address in addresses {
+        write_field(&mut addresses_bytes, &address.to_vec())?;
+    }
+    write_field(bytes, &addresses_bytes)
+}
+
+fn decode_addresses(bytes: &mut &[u8]) -> io::Result<Vec<Multiaddr>> {
+    let addresses_bytes = read_field(bytes)?;
+    let mut addresses_bytes = addresses_bytes.as_slice();
+    let mut addresses = Vec::new();
+    while !addresses_bytes.is_empty() {
+        let address = Multiaddr::try_from(read_field(&mut addresses_bytes)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        addresses.push(address);
+    }
+    Ok(addresses)
+}
+
+fn decode_peer_id(bytes: &[u8]) -> io::Result<PeerId> {
+    PeerId::from_bytes(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// The expiration is saved as a unix timestamp in milliseconds, empty if the entry never expires
+fn encode_expires(expires: Option<Instant>, bytes: &mut Vec<u8>) -> io::Result<()> {
+    let timestamp = expires.map(|expires| {
+        let expires = SystemTime::now() + expires.saturating_duration_since(Instant::now());
+        let timestamp = expires.duration_since(UNIX_EPOCH).unwrap_or_default();
+        (timestamp.as_millis() as u64).to_be_bytes()
+    });
+    write_field(bytes, timestamp.as_ref().map_or(&[], |timestamp| timestamp))
+}
+
+/// The expiration of an entry, None if the entry already expired
+fn decode_expires(bytes: &mut &[u8]) -> io::Result<Option<Option<Instant>>> {
+    let timestamp = read_field(bytes)?;
+    if timestamp.is_empty() {
+        return Ok(Some(None));
+    }
+    let timestamp = <[u8; 8]>::try_from(timestamp.as_slice())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let expires = UNIX_EPOCH + Duration::from_millis(u64::from_be_bytes(timestamp));
+    Ok(expires
+        .duration_since(SystemTime::now())
+        .ok()
+        .filter(|remaining| !remaining.is_zero())
+        .map(|remaining| Some(Instant::now() + remaining)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("pragmalink-store-{}", PeerId::random()))
+    }
+
+    #[test]
+    fn restore_saved_store() {
+        let directory = temp_dir();
+        let local_peer_id = PeerId::random();
+        let mut store = PersistentStore::open(local_peer_id, Some(directory.clone())).unwrap();
+        let record = Record {
+            key: RecordKey::new(b"record"),
+            value: b"value".to_vec(),
+            publisher: Some(local_peer_id),
+            expires: Some(Instant::now() + Duration::from_secs(60)),
+        };
+        let expired = Record {
+            key: RecordKey::new(b"expired"),
+            value: b"value".to_vec(),
+            publisher: None,
+            expires: Some(Instant::now() - Duration::from_secs(1)),
+        };
+        let provider = ProviderRecord::new(
+            RecordKey::new(b"provided"),
+            local_peer_id,
+            vec!["/ip4/1.2.3.4/tcp/1123".parse().unwrap()],
+        );
+        let peer = (
+            PeerId::random(),
+            vec!["/ip4/5.6.7.8/udp/1123/quic-v1".parse().unwrap()],
+        );
+        store.put(record.clone()).unwrap();
+        store.put(expired.clone()).unwrap();
+        store.add_provider(provider.clone()).unwrap();
+        store
+            .snapshot([peer.clone()].into_iter())
+            .unwrap()
+            .unwrap()
+            .write()
+            .unwrap();
+
+        let store = PersistentStore::open(local_peer_id, Some(directory.clone())).unwrap();
+        let restored = store.get(&record.key).unwrap();
+        assert_eq!(restored.value, record.value);
+        assert_eq!(restored.publisher, record.publisher);
+        assert!(restored.expires.is_some());
+        assert!(store.get(&expired.key).is_none());
+        let provided: Vec<_> = store.provided().map(|record| record.into_owned()).collect();
+        assert_eq!(provided, vec![provider]);
+        assert_eq!(store.load_peers(), vec![peer]);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn skip_corrupted_files() {
+        let directory = temp_dir();
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join(RECORDS_FILE), [0, 0, 0, 9, 1]).unwrap();
+        fs::write(directory.join(PEERS_FILE), b"not a peer").unwrap();
+
+        let store = PersistentStore::open(PeerId::random(), Some(directory.clone())).unwrap();
+        assert_eq!(store.records().count(), 0);
+        assert!(store.load_peers().is_empty());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn keep_unsaved_records_pending() {
+        let directory = temp_dir();
+        let mut store = PersistentStore::open(PeerId::random(), Some(directory.clone())).unwrap();
+        let record = |key: &[u8]| Record::new(RecordKey::new(&key), b"value".to_vec());
+        let version = |store: &mut PersistentStore| {
+            store.snapshot(std::iter::empty()).unwrap().unwrap().version
+        };
+        assert_eq!(version(&mut store), None);
+        store.put(record(b"first")).unwrap();
+        // The write of the snapshot failed, the records are included in the next one
+        assert_eq!(version(&mut store), Some(1));
+        let written = version(&mut store).unwrap();
+        // A record stored while the snapshot is written is included in the next one
+        store.put(record(b"second")).unwrap();
+        store.saved(written);
+        assert_eq!(version(&mut store), Some(2));
+        store.saved(2);
+        store.saved(written);
+        assert_eq!(version(&mut store), None);
+        fs::remove_dir_all(directory).ok();
+    }
+}
@@ -0,0 +1,261 @@
+use crate::{CHANNEL_SIZE, types::ReceivedMessage};
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::sync::{broadcast, mpsc};
+
+/// What happens when a topic receiver is full
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// The oldest messages of the receiver are dropped
+    #[default]
+    DropOldest,
+    /// The new messages are dropped until the receiver has room
+    DropNewest,
+    /// The node waits for the receiver to have room, up to the backpressure timeout, then drops the message.
+    /// The node does not process any other event while it waits, so a slow consumer stalls the whole node
+    Backpressure,
+}
+
+/// The settings of a topic receiver
+#[derive(Clone, Copy, Debug)]
+pub struct SubscriptionSettings {
+    /// The number of messages the receiver can hold
+    pub capacity: NonZeroUsize,
+    /// What happens when the receiver is full
+    pub overflow: OverflowPolicy,
+    /// How long the node waits for a full receiver with [`OverflowPolicy::Backpressure`]
+    pub backpressure_timeout: Duration,
+}
+
+impl Default for SubscriptionSettings {
+    fn default() -> Self {
+        Self {
+            capacity: NonZeroUsize::new(CHANNEL_SIZE).expect("CHANNEL_SIZE is not zero"),
+            overflow: OverflowPolicy::default(),
+            backpressure_timeout: Duration::from_millis(100),
+        }
+    }
+}
+
+/// The delivery metrics of a topic, shared by all the receivers of the topic
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TopicMetrics {
+    /// Messages sent to the topic receivers
+    pub delivered: u64,
+    /// Messages dropped because a receiver was full
+    pub lagged: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct TopicCounters {
+    delivered: AtomicU64,
+    lagged: AtomicU64,
+}
+
+impl TopicCounters {
+    pub(crate) fn snapshot(&self) -> TopicMetrics {
+        TopicMetrics {
+            delivered: self.delivered.load(Ordering::Relaxed),
+            lagged: self.lagged.load(Ordering::Relaxed),
+        }
+    }
+    fn add_lagged(&self, count: u64) {
+        self.lagged.fetch_add(count, Ordering::Relaxed);
+    }
+}
+
+/// A dedicated receiver of the messages of a topic
+pub struct TopicReceiver {
+    topic: String,
+    inner: ReceiverInner,
+    counters: Arc<TopicCounters>,
+}
+
+enum ReceiverInner {
+    Broadcast(broadcast::Receiver<ReceivedMessage>),
+    Mpsc(mpsc::Receiver<ReceivedMessage>),
+}
+
+impl TopicReceiver {
+    /// The name of the topic
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+    /// Receive the next message of the topic, returns None once the topic is unsubscribed or the node stopped
+    pub async fn recv(&mut self) -> Option<ReceivedMessage> {
+        match &mut self.inner {
+            ReceiverInner::Broadcast(rx) => loop {
+                match rx.recv().await {
+                    Ok(message) => return Some(message),
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        tracing::warn!(
+                            "Receiver of topic {} lagged by {count} messages",
+                            self.topic
+                        );
+                        self.counters.add_lagged(count);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            },
+            ReceiverInner::Mpsc(rx) => rx.recv().await,
+        }
+    }
+    /// The delivery metrics of the topic
+    pub fn metrics(&self) -> TopicMetrics {
+        self.counters.snapshot()
+    }
+}
+
+/// The node side of a topic receiver
+pub(crate) enum TopicSender {
+    Broadcast(broadcast::Sender<ReceivedMessage>),
+    DropNewest(mpsc::Sender<ReceivedMessage>),
+    Backpressure(mpsc::Sender<ReceivedMessage>, Duration),
+}
+
+impl TopicSender {
+    /// Create a topic receiver and the matching sender
+    pub(crate) fn channel(
+        topic: String,
+        settings: SubscriptionSettings,
+        counters: Arc<TopicCounters>,
+    ) -> (Self, TopicReceiver) {
+        let (sender, inner) = match settings.overflow {
+            OverflowPolicy::DropOldest => {
+                let (tx, rx) = broadcast::channel(settings.capacity.get());
+                (Self::Broadcast(tx), ReceiverInner::Broadcast(rx))
+            }
+            OverflowPolicy::DropNewest => {
+                let (tx, rx) = mpsc::channel(settings.capacity.get());
+                (Self::DropNewest(tx), ReceiverInner::Mpsc(rx))
+            }
+            OverflowPolicy::Backpressure => {
+                let (tx, rx) = mpsc::channel(settings.capacity.get());
+                let sender = Self::Backpressure(tx, settings.backpressure_timeout);
+                (sender, ReceiverInner::Mpsc(rx))
+            }
+        };
+        let receiver = TopicReceiver {
+            topic,
+            inner,
+            counters,
+        };
+        (sender, receiver)
+    }
+    pub(crate) fn is_closed(&self) -> bool {
+        match self {
+            Self::Broadcast(tx) => tx.receiver_count() == 0,
+            Self::DropNewest(tx) | Self::Backpressure(tx, _) => tx.is_closed(),
+        }
+    }
+    /// Send a message to the receiver, applying the overflow policy
+    pub(crate) async fn send(&self, message: ReceivedMessage, counters: &TopicCounters) {
+        let delivered = match self {
+            Self::Broadcast(tx) => tx.send(message).is_ok(),
+            Self::Backpressure(tx, timeout) => match tx.send_timeout(message, *timeout).await {
+                Ok(()) => true,
+                Err(mpsc::error::SendTimeoutError::Timeout(_)) => {
+                    counters.add_lagged(1);
+                    false
+                }
+                Err(mpsc::error::SendTimeoutError::Closed(_)) => false,
+            },
+            Self::DropNewest(tx) => match tx.try_send(message) {
+                Ok(()) => true,
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    counters.add_lagged(1);
+                    false
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => false,
+            },
+        };
+        if delivered {
+            counters.delivered.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::PeerId;
+    use libp2p_gossipsub::MessageId;
+    use std::time::SystemTime;
+
+    fn message(data: u8) -> ReceivedMessage {
+        ReceivedMessage {
+            message_id: MessageId::new(&[data]),
+            source: None,
+            propagation_source: PeerId::random(),
+            sequence_number: None,
+            received_at: SystemTime::now(),
+            source_certified: None,
+            data: vec![data],
+            topic: "topic".to_string(),
+        }
+    }
+
+    fn channel(overflow: OverflowPolicy) -> (TopicSender, TopicReceiver, Arc<TopicCounters>) {
+        let settings = SubscriptionSettings {
+            capacity: NonZeroUsize::new(1).unwrap(),
+            overflow,
+            backpressure_timeout: Duration::from_millis(10),
+        };
+        let counters = Arc::new(TopicCounters::default());
+        let (sender, receiver) =
+            TopicSender::channel("topic".to_string(), settings, counters.clone());
+        (sender, receiver, counters)
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_keeps_the_newest_message() {
+        let (sender, mut receiver, counters) = channel(OverflowPolicy::DropOldest);
+        sender.send(message(1), &counters).await;
+        sender.send(message(2), &counters).await;
+        assert_eq!(receiver.recv().await.unwrap().data, vec![2]);
+        let metrics = receiver.metrics();
+        assert_eq!((metrics.delivered, metrics.lagged), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn drop_newest_keeps_the_oldest_message() {
+        let (sender, mut receiver, counters) = channel(OverflowPolicy::DropNewest);
+        sender.send(message(1), &counters).await;
+        sender.send(message(2), &counters).await;
+        assert_eq!(receiver.recv().await.unwrap().data, vec![1]);
+        let metrics = receiver.metrics();
+        assert_eq!((metrics.delivered, metrics.lagged), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn backpressure_waits_for_the_receiver() {
+        let (sender, mut receiver, counters) = channel(OverflowPolicy::Backpressure);
+        sender.send(message(1), &counters).await;
+        let consumer = tokio::spawn(async move {
+            let first = receiver.recv().await.unwrap();
+            let second = receiver.recv().await.unwrap();
+            (first.data, second.data, receiver)
+        });
+        sender.send(message(2), &counters).await;
+        let (first, second, receiver) = consumer.await.unwrap();
+        assert_eq!((first, second), (vec![1], vec![2]));
+        let metrics = receiver.metrics();
+        assert_eq!((metrics.delivered, metrics.lagged), (2, 0));
+    }
+
+    #[tokio::test]
+    async fn backpressure_drops_after_the_timeout() {
+        let (sender, mut receiver, counters) = channel(OverflowPolicy::Backpressure);
+        sender.send(message(1), &counters).await;
+        sender.send(message(2), &counters).await;
+        assert_eq!(receiver.recv().await.unwrap().data, vec![1]);
+        let metrics = receiver.metrics();
+        assert_eq!((metrics.delivered, metrics.lagged), (1, 1));
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{
+    error::{DecodeError, EncodeError},
+    traits::Codec,
+    types::ReceivedMessage,
+};
+use std::{marker::PhantomData, sync::Arc};
+
+/// Check that a received payload can be decoded, used by the node to validate the messages of typed topics
+pub type TopicDecoder = Arc<dyn Fn(&[u8]) -> Result<(), DecodeError> + Send + Sync>;
+
+/// A gossipsub topic carrying payloads of type T, encoded with a codec
+pub struct Topic<T> {
+    name: String,
+    codec: Arc<dyn Codec<T>>,
+    _payload: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Topic<T> {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            codec: self.codec.clone(),
+            _payload: PhantomData,
+        }
+    }
+}
+
+impl<T: 'static> Topic<T> {
+    /// Create a typed topic of a given name, encoded with a given codec
+    pub fn new(name: impl Into<String>, codec: impl Codec<T>) -> Self {
+        Self {
+            name: name.into(),
+            codec: Arc::new(codec),
+            _payload: PhantomData,
+        }
+    }
+    /// The name of the topic
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn encode(&self, value: &T) -> Result<Vec<u8>, EncodeError> {
+        self.codec.encode(value)
+    }
+    pub fn decode(&self, bytes: &[u8]) -> Result<T, DecodeError> {
+        self.codec.decode(bytes)
+    }
+    /// A decoder that only checks the payloads, to reject undecodable messages
+    pub(crate) fn decoder(&self) -> TopicDecoder {
+        let codec = self.codec.clone();
+        Arc::new(move |bytes| codec.decode(bytes).map(|_| ()))
+    }
+}
+
+#[cfg(feature = "json")]
+impl<T> Topic<T>
+where
+    T: serde::Serialize + serde::de::DeserializeOwned + 'static,
+{
+    /// Create a typed topic encoded in JSON
+    pub fn json(name: impl Into<String>) -> Self {
+        Self::new(name, crate::codec::JsonCodec)
+    }
+}
+
+#[cfg(feature = "bincode")]
+impl<T> Topic<T>
+where
+    T: serde::Serialize + serde::de::DeserializeOwned + 'static,
+{
+    /// Create a typed topic encoded with bincode
+    pub fn bincode(name: impl Into<String>) -> Self {
+        Self::new(name, crate::codec::BincodeCodec)
+    }
+}
+
+#[cfg(feature = "prost")]
+impl<T> Topic<T>
+where
+    T: prost::Message + Default + 'static,
+{
+    /// Create a typed topic encoded as protobuf messages
+    pub fn prost(name: impl Into<String>) -> Self {
+        Self::new(name, crate::codec::ProstCodec)
+    }
+}
+
+/// A decoded payload received on a typed topic, with the message metadata
+#[derive(Clone, Debug)]
+pub struct Typed<T> {
+    pub value: T,
+    pub message: ReceivedMessage,
+}
@@ -1,3 +1,5 @@
+use crate::error::{DecodeError, EncodeError};
+
 pub trait AsHex {
     fn as_hex_string(&self) -> String;
 }
@@ -20,3 +22,9 @@ impl AsBytes for &[u8] {
         self.to_vec()
     }
 }
+
+/// Encode and decode the payloads of a typed topic
+pub trait Codec<T>: Send + Sync + 'static {
+    fn encode(&self, value: &T) -> Result<Vec<u8>, EncodeError>;
+    fn decode(&self, bytes: &[u8]) -> Result<T, DecodeError>;
+}
@@ -1,7 +1,199 @@
-use libp2p::identify;
+use crate::{
+    bootstrap::BootstrapStatus,
+    config::ConnectionLimitsSettings,
+    dht::{DhtQueryResult, DhtRecord},
+    error::P2pError,
+    subscription::{SubscriptionSettings, TopicMetrics, TopicReceiver},
+    topic::TopicDecoder,
+};
+use libp2p::{Multiaddr, PeerId, identify};
+use libp2p_gossipsub::{MessageAcceptance, MessageId};
+use std::{
+    collections::{HashMap, HashSet},
+    num::NonZeroUsize,
+    time::SystemTime,
+};
+use tokio::sync::oneshot;
+
+/// The sending half of a oneshot channel used by the node to answer a request
+pub type Responder<T> = oneshot::Sender<T>;
+
+/// A connection waiting for authorization, with the channel to send the verdict (true / false)
+pub type ConnectionAuthorizationRequest = (ReceivedConnection, oneshot::Sender<bool>);
 
 pub enum P2pRequest {
-    Broadcast(String, Vec<u8>),
+    /// Publish data on a topic, the optional responder receives the message id or the publish error
+    Broadcast(
+        String,
+        Vec<u8>,
+        Option<Responder<Result<MessageId, P2pError>>>,
+    ),
+    /// Subscribe to a topic, the responder receives a dedicated receiver of the topic messages.
+    /// The optional decoder of a typed topic is installed once subscribed, messages that cannot be decoded are rejected
+    Subscribe(
+        String,
+        SubscriptionSettings,
+        Option<TopicDecoder>,
+        Responder<Result<TopicReceiver, P2pError>>,
+    ),
+    Unsubscribe(String, Responder<Result<bool, P2pError>>),
+    /// The delivery metrics of each topic, by topic name
+    TopicMetrics(Responder<HashMap<String, TopicMetrics>>),
+    ConnectedPeers(Responder<Vec<PeerId>>),
+    /// Store a record in the DHT, on at least quorum peers
+    PutRecord(
+        Vec<u8>,
+        Vec<u8>,
+        NonZeroUsize,
+        Responder<Result<DhtQueryResult<()>, P2pError>>,
+    ),
+    /// Look up a record in the DHT, the query stops once quorum records are found
+    GetRecord(
+        Vec<u8>,
+        NonZeroUsize,
+        Responder<Result<DhtQueryResult<Vec<DhtRecord>>, P2pError>>,
+    ),
+    /// Announce the node as a provider of a key
+    StartProviding(Vec<u8>, Responder<Result<DhtQueryResult<()>, P2pError>>),
+    /// Look up the providers of a key
+    GetProviders(
+        Vec<u8>,
+        Responder<Result<DhtQueryResult<HashSet<PeerId>>, P2pError>>,
+    ),
+    /// Look up the peers closest to a peer id
+    FindClosestPeers(
+        PeerId,
+        Responder<Result<DhtQueryResult<Vec<PeerId>>, P2pError>>,
+    ),
+    /// Send a direct request to a peer, the responder receives the peer response
+    SendRequest(PeerId, Vec<u8>, Responder<Result<Vec<u8>, P2pError>>),
+    /// The bootstrap state of the node: connected peers and redial attempts
+    BootstrapStatus(Responder<BootstrapStatus>),
+    /// The connected peers, with their addresses, connections and certificate state
+    PeerTable(Responder<HashMap<PeerId, PeerInfo>>),
+    /// The gossipsub score of the known peers, empty if peer scoring is disabled
+    PeerScores(Responder<HashMap<PeerId, f64>>),
+    Dial(Multiaddr, Responder<Result<(), P2pError>>),
+    ListenAddrs(Responder<Vec<Multiaddr>>),
+    /// Listen on a new address, the bound addresses are reported with [`P2pEvent::NewListenAddr`]
+    AddListener(Multiaddr, Responder<Result<(), P2pError>>),
+    /// Stop listening on an address, the responder receives false if the node was not listening on it
+    RemoveListener(Multiaddr, Responder<bool>),
+    /// Block a peer, closing its connections
+    BlockPeer(PeerId, Responder<()>),
+    UnblockPeer(PeerId, Responder<()>),
+    /// Add a peer to the allow list, fails if no allow list is configured
+    AllowPeer(PeerId, Responder<Result<(), P2pError>>),
+    /// Remove a peer from the allow list, closing its connections
+    DisallowPeer(PeerId, Responder<Result<(), P2pError>>),
+    /// Replace the connection limits, existing connections are kept
+    SetConnectionLimits(ConnectionLimitsSettings, Responder<()>),
+    Shutdown(Responder<()>),
+}
+
+/// A direct request received from a peer, with the channel to send the response
+pub type InboundRequest = (ReceivedRequest, oneshot::Sender<Vec<u8>>);
+
+/// A direct request received from an authorized peer
+#[derive(Clone, Debug)]
+pub struct ReceivedRequest {
+    /// The peer that sent the request
+    pub peer_id: PeerId,
+    /// Whether the peer was authorized with a certificate
+    pub certified: bool,
+    pub data: Vec<u8>,
+}
+
+/// A received message waiting for validation, with the channel to send the verdict (Accept / Reject / Ignore)
+pub type MessageValidationRequest = (ReceivedMessage, oneshot::Sender<MessageAcceptance>);
+
+/// Events emitted by the node, received through [`crate::handle::P2pHandle::events`]
+#[derive(Clone, Debug)]
+pub enum P2pEvent {
+    /// A remote peer subscribed to a topic
+    TopicPeerSubscribed { peer_id: PeerId, topic: String },
+    /// A remote peer unsubscribed from a topic
+    TopicPeerUnsubscribed { peer_id: PeerId, topic: String },
+    /// A peer was rejected by the connection authorization, it is disconnected and denied if the rejection is permanent
+    PeerRejected {
+        peer_id: PeerId,
+        reason: RejectionReason,
+    },
+    /// A peer score dropped below the graylist threshold, its messages are ignored
+    PeerGraylisted { peer_id: PeerId, score: f64 },
+    /// The node is listening on a new address, with the resolved port when listening on port 0
+    NewListenAddr { address: Multiaddr },
+    /// The node stopped listening on an address
+    ExpiredListenAddr { address: Multiaddr },
+}
+
+/// Peer lifecycle events, received through [`crate::handle::P2pHandle::peer_events`]
+#[derive(Clone, Debug)]
+pub enum PeerEvent {
+    /// A first connection with a peer was established
+    Connected { peer_id: PeerId, address: Multiaddr },
+    /// A peer was accepted by the connection authorization
+    Authorized { peer_id: PeerId, certified: bool },
+    /// A peer was rejected by the connection authorization, it is disconnected and denied if the rejection is permanent
+    Rejected {
+        peer_id: PeerId,
+        reason: RejectionReason,
+    },
+    /// The last connection with a peer was closed, with the error that closed it if any
+    Disconnected {
+        peer_id: PeerId,
+        cause: Option<String>,
+    },
+    /// An outgoing connection failed, the peer id is unknown when dialing an address
+    DialFailed {
+        peer_id: Option<PeerId>,
+        error: String,
+    },
+}
+
+/// The certificate state of a connected peer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CertificateState {
+    /// The peer is not authorized yet
+    Pending,
+    /// The peer was authorized with a certificate
+    Certified,
+    /// The peer was authorized without a certificate
+    Uncertified,
+}
+
+/// A connected peer, as tracked by the node
+#[derive(Clone, Debug)]
+pub struct PeerInfo {
+    /// The remote addresses of the connections with the peer
+    pub addresses: HashSet<Multiaddr>,
+    /// The number of established connections with the peer
+    pub connections: usize,
+    /// When the first connection with the peer was established
+    pub connected_since: SystemTime,
+    pub certificate: CertificateState,
+    /// The agent version sent by the peer, once identified
+    pub agent_version: Option<String>,
+}
+
+/// Why a peer was rejected
+#[derive(Clone, Debug, thiserror::Error)]
+pub enum RejectionReason {
+    #[error("the connection was not authorized")]
+    NotAuthorized,
+    #[error("invalid identity: {0}")]
+    InvalidIdentity(String),
+    #[error("no authorization verdict was received in time")]
+    AuthorizationTimeout,
+    #[error("the certificate exchange failed: {0}")]
+    CertificateExchangeFailed(String),
+}
+
+impl RejectionReason {
+    /// Whether the peer is blocked, a peer rejected for a transient reason is only disconnected
+    pub fn is_permanent(&self) -> bool {
+        matches!(self, Self::NotAuthorized | Self::InvalidIdentity(_))
+    }
 }
 
 pub struct ReceivedConnection {
@@ -9,7 +201,8 @@ pub struct ReceivedConnection {
     pub pubkey: [u8; 32],
     pub listen_addrs: Vec<String>,
     pub observed_addr: String,
-    pub certificate: Option<String>,
+    /// The binary protobuf certificate received through the certificate exchange, None if the peer is not certified
+    pub certificate: Option<Vec<u8>>,
 }
 
 impl TryFrom<identify::Event> for ReceivedConnection {
@@ -17,6 +210,9 @@ impl TryFrom<identify::Event> for ReceivedConnection {
     fn try_from(event: identify::Event) -> anyhow::Result<Self> {
         match event {
             identify::Event::Received { peer_id, info, .. } => {
+                if info.public_key.to_peer_id() != peer_id {
+                    anyhow::bail!("The identify public key does not match the peer id");
+                }
                 let pubkey = info.public_key.try_into_ed25519()?.to_bytes();
                 let listen_addrs = info
                     .listen_addrs
@@ -24,12 +220,11 @@ impl TryFrom<identify::Event> for ReceivedConnection {
                     .map(|addr| addr.to_string())
                     .collect();
                 let observed_addr = info.observed_addr.to_string();
-                let certificate = extract_certificate_from_agent_version(&info.agent_version);
                 Ok(ReceivedConnection {
                     peer_id: peer_id.to_string(),
                     pubkey,
                     listen_addrs,
-                    certificate,
+                    certificate: None,
                     observed_addr,
                 })
             }
@@ -41,12 +236,20 @@ impl TryFrom<identify::Event> for ReceivedConnection {
     }
 }
 
-fn extract_certificate_from_agent_version(agent_version: &str) -> Option<String> {
-    agent_version.split('/').nth(3).map(|s| s.into())
-}
- #[derive(Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct ReceivedMessage {
+    /// The gossipsub id of the message
+    pub message_id: MessageId,
+    /// The peer that published the message
     pub source: Option<String>,
+    /// The peer that forwarded the message to this node, can differ from the source
+    pub propagation_source: PeerId,
+    /// The sequence number set by the source
+    pub sequence_number: Option<u64>,
+    /// When the message was received by this node
+    pub received_at: SystemTime,
+    /// Whether the source was authorized with a certificate, None if the source is not an authorized peer of this node
+    pub source_certified: Option<bool>,
     pub data: Vec<u8>,
     pub topic: String,
-}
\ No newline at end of file
+}